@@ -0,0 +1,131 @@
+use cortex_m::peripheral::DWT;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::Rectangle,
+};
+use embedded_text::TextBox;
+use stm32f7xx_hal::rcc::Clocks;
+
+use crate::display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH, TEXT_COLOUR};
+
+const SCREEN_PIXELS: u32 = DISPLAY_WIDTH as u32 * DISPLAY_HEIGHT as u32;
+
+/// Result of timing a display operation with the DWT cycle counter.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    pub cycles: u32,
+    /// Zero when the pixel count is not known, see `Benchmark::text`.
+    pub pixels: u64,
+    pub frames: u32,
+    pub hclk: u32,
+}
+
+impl BenchResult {
+    pub fn micros(&self) -> u32 {
+        (self.cycles as u64 * 1_000_000 / self.hclk as u64) as u32
+    }
+
+    pub fn pixels_per_second(&self) -> u32 {
+        if self.cycles == 0 {
+            return 0;
+        }
+        (self.pixels as u128 * self.hclk as u128 / self.cycles as u128) as u32
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        self.frames as f32 * self.hclk as f32 / self.cycles as f32
+    }
+}
+
+/// Times display operations, the cycle counter wraps after ~22 seconds at
+/// 192 MHz so keep the frame counts low enough to stay under that.
+pub struct Benchmark {
+    hclk: u32,
+}
+
+impl Benchmark {
+    pub fn new(clocks: &Clocks) -> Self {
+        let mut cp = unsafe { cortex_m::Peripherals::steal() };
+
+        cp.DCB.enable_trace();
+        DWT::unlock();
+        cp.DWT.enable_cycle_counter();
+
+        Self {
+            hclk: clocks.hclk().to_Hz(),
+        }
+    }
+
+    /// Runs `f` once per frame and reports `pixels_per_frame` pixels written
+    /// for each frame, use this to compare custom drawing strategies.
+    pub fn measure<F>(
+        &self,
+        display: &mut Display,
+        frames: u32,
+        pixels_per_frame: u32,
+        mut f: F,
+    ) -> BenchResult
+    where
+        F: FnMut(&mut Display, u32),
+    {
+        let start = DWT::cycle_count();
+        for frame in 0..frames {
+            f(display, frame);
+        }
+        let cycles = DWT::cycle_count().wrapping_sub(start);
+
+        BenchResult {
+            cycles,
+            pixels: pixels_per_frame as u64 * frames as u64,
+            frames,
+            hclk: self.hclk,
+        }
+    }
+
+    /// Fills the whole screen `frames` times, alternating between two colours
+    /// so every frame changes every pixel.
+    pub fn full_screen_fill(&self, display: &mut Display, frames: u32) -> BenchResult {
+        self.measure(display, frames, SCREEN_PIXELS, |display, frame| {
+            let colour = if frame % 2 == 0 {
+                Rgb565::BLACK
+            } else {
+                Rgb565::WHITE
+            };
+            display.clear(colour);
+        })
+    }
+
+    /// Fills `area` with a solid colour `frames` times.
+    pub fn window_write(&self, display: &mut Display, area: Rectangle, frames: u32) -> BenchResult {
+        let pixels = area.size.width * area.size.height;
+        self.measure(display, frames, pixels, |display, frame| {
+            let colour = if frame % 2 == 0 {
+                Rgb565::BLUE
+            } else {
+                Rgb565::RED
+            };
+            display.display.fill_solid(&area, colour).unwrap();
+        })
+    }
+
+    /// Renders `text` into a full screen text box `frames` times. Only the
+    /// glyph pixels are written, so no pixel count is reported, compare the
+    /// fps instead.
+    pub fn text(&self, display: &mut Display, text: &str, frames: u32) -> BenchResult {
+        let bounds = Rectangle::new(
+            Point::zero(),
+            Size::new(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32),
+        );
+        let character_style = MonoTextStyle::new(&FONT_6X10, TEXT_COLOUR);
+        self.measure(display, frames, 0, |display, _| {
+            TextBox::new(text, bounds, character_style)
+                .draw(&mut display.display)
+                .unwrap();
+        })
+    }
+}
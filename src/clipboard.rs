@@ -0,0 +1,141 @@
+use heapless::String;
+
+use crate::hal::pac::{PWR, RCC};
+
+pub const CLIPBOARD_SIZE: usize = 256;
+
+/// Start of the 4 KiB backup SRAM.
+const BKPSRAM_START: u32 = 0x4002_4000;
+
+const MAGIC: [u8; 4] = *b"CLIP";
+/// Magic followed by the text length as a little endian u16.
+const HEADER_BYTES: usize = MAGIC.len() + 2;
+
+const RCC_AHB1ENR_BKPSRAMEN: u32 = 1 << 18;
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const PWR_CR1_DBP: u32 = 1 << 8;
+const PWR_CSR1_BRR: u32 = 1 << 3;
+const PWR_CSR1_BRE: u32 = 1 << 9;
+/// Polls of `PWR_CSR1_BRR` before giving up on the backup regulator.
+const BRR_TIMEOUT: u32 = 100_000;
+
+/// Text shared between the editable widgets, filled by the Cut and Copy keys
+/// and read back by Paste.
+pub struct Clipboard {
+    text: String<CLIPBOARD_SIZE>,
+    backup: bool,
+}
+
+impl Clipboard {
+    /// A clipboard that is lost on reset.
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            backup: false,
+        }
+    }
+
+    /// A clipboard mirrored in backup SRAM, so it survives resets and keeps
+    /// its content on VBAT while the rest of the board is off. The previous
+    /// content is restored if the backup SRAM holds a valid clipboard.
+    /// Returns `None` if the backup regulator does not become ready.
+    pub fn with_backup_sram() -> Option<Self> {
+        if !enable_backup_sram() {
+            return None;
+        }
+        let mut clipboard = Self {
+            text: String::new(),
+            backup: true,
+        };
+        // SAFETY: The backup SRAM was just enabled and is only used here
+        if let Some(text) = unsafe { read_backup() } {
+            clipboard.text.push_str(text).unwrap();
+        }
+        Some(clipboard)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Replaces the content with `text`, cut on a char boundary if it is
+    /// longer than `CLIPBOARD_SIZE`. Returns false if it was cut.
+    pub fn set(&mut self, text: &str) -> bool {
+        let mut end = text.len().min(CLIPBOARD_SIZE);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text.clear();
+        self.text.push_str(&text[..end]).unwrap();
+        self.save();
+        end == text.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.save();
+    }
+
+    fn save(&self) {
+        if self.backup {
+            // SAFETY: The backup SRAM was enabled by `with_backup_sram`
+            unsafe { write_backup(&self.text) };
+        }
+    }
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns false if the backup regulator is not ready after `BRR_TIMEOUT`
+/// polls.
+fn enable_backup_sram() -> bool {
+    unsafe {
+        let rcc = &*RCC::ptr();
+        let pwr = &*PWR::ptr();
+
+        rcc.apb1enr
+            .modify(|r, w| w.bits(r.bits() | RCC_APB1ENR_PWREN));
+        // Allow writes to the backup domain.
+        pwr.cr1.modify(|r, w| w.bits(r.bits() | PWR_CR1_DBP));
+        rcc.ahb1enr
+            .modify(|r, w| w.bits(r.bits() | RCC_AHB1ENR_BKPSRAMEN));
+
+        // Keep the backup SRAM powered from VBAT.
+        pwr.csr1.modify(|r, w| w.bits(r.bits() | PWR_CSR1_BRE));
+        (0..BRR_TIMEOUT).any(|_| pwr.csr1.read().bits() & PWR_CSR1_BRR != 0)
+    }
+}
+
+unsafe fn read_backup() -> Option<&'static str> {
+    let header = core::slice::from_raw_parts(BKPSRAM_START as *const u8, HEADER_BYTES);
+    if header[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    let length = u16::from_le_bytes([header[MAGIC.len()], header[MAGIC.len() + 1]]) as usize;
+    if length > CLIPBOARD_SIZE {
+        return None;
+    }
+    let text =
+        core::slice::from_raw_parts((BKPSRAM_START as usize + HEADER_BYTES) as *const u8, length);
+    core::str::from_utf8(text).ok()
+}
+
+unsafe fn write_backup(text: &str) {
+    let ptr = BKPSRAM_START as *mut u8;
+    let length = (text.len() as u16).to_le_bytes();
+    let bytes = MAGIC
+        .iter()
+        .chain(length.iter())
+        .chain(text.as_bytes().iter());
+    for (i, byte) in bytes.enumerate() {
+        ptr.add(i).write_volatile(*byte);
+    }
+}
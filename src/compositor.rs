@@ -0,0 +1,282 @@
+use core::convert::Infallible;
+
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use heapless::Vec;
+
+use crate::display::{Display, BG_COLOUR};
+
+/// Maximum number of separate dirty regions tracked between flushes, once
+/// exceeded the regions are merged into their bounding box.
+const MAX_DIRTY: usize = 8;
+
+pub const OPAQUE: u8 = 0xff;
+
+/// Blends `fg` over `bg`, an `alpha` of 0 keeps `bg` and 255 gives `fg`.
+pub fn blend(fg: Rgb565, bg: Rgb565, alpha: u8) -> Rgb565 {
+    match alpha {
+        0 => bg,
+        OPAQUE => fg,
+        _ => {
+            let a = alpha as u16;
+            let mix = |f: u8, b: u8| ((f as u16 * a + b as u16 * (255 - a) + 127) / 255) as u8;
+            Rgb565::new(
+                mix(fg.r(), bg.r()),
+                mix(fg.g(), bg.g()),
+                mix(fg.b(), bg.b()),
+            )
+        }
+    }
+}
+
+fn union(a: Rectangle, b: Rectangle) -> Rectangle {
+    match (a.bottom_right(), b.bottom_right()) {
+        (Some(a_br), Some(b_br)) => Rectangle::with_corners(
+            a.top_left.component_min(b.top_left),
+            a_br.component_max(b_br),
+        ),
+        (None, _) => b,
+        (_, None) => a,
+    }
+}
+
+/// An off-screen layer backed by a caller provided pixel buffer.
+///
+/// Layers implement `DrawTarget` using coordinates relative to their own top
+/// left corner, anything drawn marks that part of the layer as dirty.
+pub struct Layer<'a> {
+    bounds: Rectangle,
+    pixels: &'a mut [Rgb565],
+    alpha: u8,
+    visible: bool,
+    transparent: Option<Rgb565>,
+    dirty: Option<(Point, Point)>,
+}
+
+impl<'a> Layer<'a> {
+    /// `pixels` must hold at least `bounds.size.width * bounds.size.height`
+    /// pixels, stored row by row.
+    pub fn new(bounds: Rectangle, pixels: &'a mut [Rgb565]) -> Self {
+        assert!(pixels.len() >= (bounds.size.width * bounds.size.height) as usize);
+        Self {
+            bounds,
+            pixels,
+            alpha: OPAQUE,
+            visible: true,
+            transparent: None,
+            dirty: None,
+        }
+    }
+
+    /// Pixels of this colour are skipped when compositing, letting lower
+    /// layers show through.
+    pub fn with_transparent_colour(mut self, colour: Rgb565) -> Self {
+        self.transparent = Some(colour);
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha: u8) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    pub fn bounds(&self) -> Rectangle {
+        self.bounds
+    }
+
+    pub fn alpha(&self) -> u8 {
+        self.alpha
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let size = self.bounds.size;
+        if point.x < 0
+            || point.y < 0
+            || point.x as u32 >= size.width
+            || point.y as u32 >= size.height
+        {
+            None
+        } else {
+            Some(point.y as usize * size.width as usize + point.x as usize)
+        }
+    }
+
+    fn mark_dirty(&mut self, point: Point) {
+        self.dirty = Some(match self.dirty {
+            Some((min, max)) => (min.component_min(point), max.component_max(point)),
+            None => (point, point),
+        });
+    }
+
+    fn take_dirty(&mut self) -> Option<Rectangle> {
+        self.dirty.take().map(|(min, max)| {
+            Rectangle::with_corners(min + self.bounds.top_left, max + self.bounds.top_left)
+        })
+    }
+
+    fn pixel_at(&self, point: Point) -> Option<Rgb565> {
+        if !self.visible || self.alpha == 0 {
+            return None;
+        }
+        let colour = self.pixels[self.index(point - self.bounds.top_left)?];
+        if Some(colour) == self.transparent {
+            None
+        } else {
+            Some(colour)
+        }
+    }
+}
+
+impl Dimensions for Layer<'_> {
+    fn bounding_box(&self) -> Rectangle {
+        Rectangle::new(Point::zero(), self.bounds.size)
+    }
+}
+
+impl DrawTarget for Layer<'_> {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, colour) in pixels {
+            if let Some(index) = self.index(point) {
+                self.pixels[index] = colour;
+                self.mark_dirty(point);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stacks up to `N` layers over a solid background, the first layer added is
+/// the bottom one.
+pub struct Compositor<'a, const N: usize> {
+    layers: Vec<Layer<'a>, N>,
+    background: Rgb565,
+    dirty: Vec<Rectangle, MAX_DIRTY>,
+}
+
+impl<'a, const N: usize> Compositor<'a, N> {
+    pub fn new() -> Self {
+        Self {
+            layers: Vec::new(),
+            background: BG_COLOUR,
+            dirty: Vec::new(),
+        }
+    }
+
+    /// Returns the layer's index, or gives the layer back if all `N` slots
+    /// are in use.
+    pub fn add_layer(&mut self, layer: Layer<'a>) -> Result<usize, Layer<'a>> {
+        let bounds = layer.bounds;
+        self.layers.push(layer)?;
+        self.invalidate(bounds);
+        Ok(self.layers.len() - 1)
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut Layer<'a>> {
+        self.layers.get_mut(index)
+    }
+
+    pub fn set_background(&mut self, colour: Rgb565) {
+        self.background = colour;
+        self.invalidate_all();
+    }
+
+    pub fn set_alpha(&mut self, index: usize, alpha: u8) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            if layer.alpha != alpha {
+                layer.alpha = alpha;
+                let bounds = layer.bounds;
+                self.invalidate(bounds);
+            }
+        }
+    }
+
+    pub fn set_visible(&mut self, index: usize, visible: bool) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            if layer.visible != visible {
+                layer.visible = visible;
+                let bounds = layer.bounds;
+                self.invalidate(bounds);
+            }
+        }
+    }
+
+    pub fn move_layer(&mut self, index: usize, top_left: Point) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            let old = layer.bounds;
+            layer.bounds.top_left = top_left;
+            let new = layer.bounds;
+            self.invalidate(old);
+            self.invalidate(new);
+        }
+    }
+
+    /// Marks an area of the screen to be redrawn on the next flush.
+    pub fn invalidate(&mut self, area: Rectangle) {
+        if area.is_zero_sized() {
+            return;
+        }
+        if let Err(area) = self.dirty.push(area) {
+            let merged = self.dirty.iter().fold(area, |acc, r| union(acc, *r));
+            self.dirty.clear();
+            let _ = self.dirty.push(merged);
+        }
+    }
+
+    pub fn invalidate_all(&mut self) {
+        self.dirty.clear();
+        let _ = self.dirty.push(Rectangle::new(
+            Point::zero(),
+            Size::new(
+                crate::display::DISPLAY_WIDTH as u32,
+                crate::display::DISPLAY_HEIGHT as u32,
+            ),
+        ));
+    }
+
+    fn colour_at(&self, point: Point) -> Rgb565 {
+        self.layers.iter().fold(self.background, |below, layer| {
+            match layer.pixel_at(point) {
+                Some(colour) => blend(colour, below, layer.alpha),
+                None => below,
+            }
+        })
+    }
+
+    /// Redraws every region that changed since the last flush.
+    pub fn flush(&mut self, display: &mut Display) {
+        for index in 0..self.layers.len() {
+            if let Some(area) = self.layers[index].take_dirty() {
+                self.invalidate(area);
+            }
+        }
+
+        let screen = display.display.bounding_box();
+        let dirty = core::mem::take(&mut self.dirty);
+
+        for area in dirty.iter() {
+            let area = area.intersection(&screen);
+            if area.is_zero_sized() {
+                continue;
+            }
+            display
+                .display
+                .fill_contiguous(&area, area.points().map(|p| self.colour_at(p)))
+                .unwrap();
+        }
+    }
+}
+
+impl<const N: usize> Default for Compositor<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,208 @@
+use core::fmt::Write;
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Baseline, Text},
+};
+use embedded_hal::blocking::delay::DelayUs;
+use heapless::String;
+
+use crate::display::{Display, BG_COLOUR, DISPLAY_HEIGHT, DISPLAY_WIDTH, TEXT_COLOUR};
+use crate::keypad::{KeyState, ScanSource, Switch};
+
+const ROWS: u32 = 9;
+const COLUMNS: u32 = 6;
+const CELL_WIDTH: u32 = DISPLAY_WIDTH as u32 / COLUMNS;
+const CELL_HEIGHT: u32 = 22;
+const GRID_HEIGHT: u32 = CELL_HEIGHT * ROWS;
+
+/// Fill of keys that were pressed before but are released now.
+const TESTED_COLOUR: Rgb565 = Rgb565::new(0, 16, 0);
+
+const RAW_REGION: Rectangle = Rectangle::new(
+    Point::new(0, GRID_HEIGHT as i32),
+    Size::new(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32 - GRID_HEIGHT),
+);
+
+/// Keypad test screen for checking the matrix wiring.
+///
+/// Every switch is drawn where its key sits on the calculator, labelled with
+/// the key. Pressed keys are filled, keys that were pressed at some point
+/// stay dimly filled so untested keys stand out, and the raw row bytes are
+/// shown below the keys.
+pub struct KeypadTest {
+    raw: Option<[u8; 9]>,
+    tested: KeyState,
+}
+
+impl KeypadTest {
+    pub fn new() -> Self {
+        Self {
+            raw: None,
+            tested: KeyState::empty(),
+        }
+    }
+
+    /// Every switch that has been pressed since the test started.
+    pub fn tested(&self) -> KeyState {
+        self.tested
+    }
+
+    pub fn all_tested(&self) -> bool {
+        switches().all(|switch| self.tested.is_pressed(switch))
+    }
+
+    /// Forgets the tested keys, the whole screen is redrawn on the next
+    /// update.
+    pub fn reset(&mut self) {
+        self.raw = None;
+        self.tested = KeyState::empty();
+    }
+
+    /// Shows the scanned `raw` rows, only the keys that changed since the
+    /// last update are redrawn.
+    pub fn update(&mut self, display: &mut Display, raw: [u8; 9]) {
+        let state = KeyState::from_rows(raw);
+        let changed = match self.raw {
+            Some(previous) if previous == raw => return,
+            Some(previous) => state.changed_since(KeyState::from_rows(previous)),
+            None => {
+                display.clear(BG_COLOUR);
+                KeyState(u64::MAX)
+            }
+        };
+
+        for switch in state.iter() {
+            self.tested.insert(switch);
+        }
+        for switch in switches().filter(|s| changed.is_pressed(*s)) {
+            self.draw_key(display, switch, state.is_pressed(switch));
+        }
+        draw_raw(display, raw);
+
+        self.raw = Some(raw);
+    }
+
+    /// Scans `matrix` and updates the screen until every key has been
+    /// pressed and the keypad is released again, or until the `exit` chord
+    /// is held. A key that never lights up keeps the test running, so a
+    /// faulty switch or row is easy to spot. Returns true if every key was
+    /// tested, an empty `exit` chord disables the early exit.
+    pub fn run(
+        &mut self,
+        display: &mut Display,
+        matrix: &mut impl ScanSource,
+        delay: &mut impl DelayUs<u32>,
+        exit: KeyState,
+    ) -> bool {
+        loop {
+            let raw = matrix.scan(delay);
+            self.update(display, raw);
+            let state = KeyState::from_rows(raw);
+            if self.all_tested() && state.is_empty() {
+                return true;
+            }
+            if !exit.is_empty() && state.contains(exit) {
+                return false;
+            }
+        }
+    }
+
+    fn draw_key(&self, display: &mut Display, switch: Switch, pressed: bool) {
+        let cell = cell(switch);
+
+        let (fill, text) = if pressed {
+            (TEXT_COLOUR, BG_COLOUR)
+        } else if self.tested.is_pressed(switch) {
+            (TESTED_COLOUR, TEXT_COLOUR)
+        } else {
+            (BG_COLOUR, TEXT_COLOUR)
+        };
+        cell.into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(fill)
+                .stroke_color(TEXT_COLOUR)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(&mut display.display)
+        .unwrap();
+
+        let name = switch.to_key().name();
+        // Characters of the key name that fit in the cell, leaving some
+        // padding.
+        let label_chars = (cell.size.width / 6 - 1) as usize;
+        let label = &name[..name.len().min(label_chars)];
+        Text::with_baseline(
+            label,
+            cell.top_left + Point::new(3, 6),
+            MonoTextStyle::new(&FONT_6X10, text),
+            Baseline::Top,
+        )
+        .draw(&mut display.display)
+        .unwrap();
+    }
+}
+
+impl Default for KeypadTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn switches() -> impl Iterator<Item = Switch> {
+    (0..(ROWS * COLUMNS) as usize).filter_map(Switch::from_index)
+}
+
+/// Where `switch` is drawn, leaving a gap to the next cell. The first two
+/// rows hold the navigation keys as on the calculator: the D-pad on the
+/// left, Home over Power in the middle and OK and Back on the right. The
+/// other rows match the calculator's, the last four have five wider keys.
+fn cell(switch: Switch) -> Rectangle {
+    let (row, column) = switch.position().unwrap();
+    // Column, row, columns spanned and rows spanned.
+    let (x, y, width, height) = match switch {
+        Switch::R0C1 => (0, 0, 1, 2),
+        Switch::R0C2 => (1, 0, 1, 1),
+        Switch::R0C3 => (1, 1, 1, 1),
+        Switch::R0C4 => (2, 0, 1, 2),
+        Switch::R1C1 => (3, 0, 1, 1),
+        Switch::R1C3 => (3, 1, 1, 1),
+        Switch::R0C5 => (4, 0, 1, 2),
+        Switch::R0C6 => (5, 0, 1, 2),
+        _ => (column as u32, row as u32, 1, 1),
+    };
+    let cell_width = if row >= 5 {
+        DISPLAY_WIDTH as u32 / 5
+    } else {
+        CELL_WIDTH
+    };
+    Rectangle::new(
+        Point::new((x * cell_width) as i32, (y * CELL_HEIGHT) as i32),
+        Size::new(width * cell_width - 2, height * CELL_HEIGHT - 2),
+    )
+}
+
+fn draw_raw(display: &mut Display, raw: [u8; 9]) {
+    let mut text: String<32> = String::new();
+    text.push_str("Raw:").unwrap();
+    for row in raw {
+        write!(text, " {:02x}", row).unwrap();
+    }
+
+    RAW_REGION
+        .into_styled(PrimitiveStyleBuilder::new().fill_color(BG_COLOUR).build())
+        .draw(&mut display.display)
+        .unwrap();
+    Text::with_baseline(
+        &text,
+        RAW_REGION.top_left + Point::new(4, 8),
+        MonoTextStyle::new(&FONT_6X10, TEXT_COLOUR),
+        Baseline::Top,
+    )
+    .draw(&mut display.display)
+    .unwrap();
+}
@@ -27,6 +27,9 @@ use embedded_graphics::{
 use embedded_hal::blocking::delay::DelayUs;
 use embedded_text::{plugin::tail::Tail, TextBox};
 
+use crate::clipboard::Clipboard;
+use crate::keypad::Key;
+
 pub const DISPLAY_WIDTH: u16 = 320;
 pub const DISPLAY_HEIGHT: u16 = 240;
 
@@ -38,6 +41,17 @@ const TOP_LINES: usize = 38;
 const BOTTOM_STRING_SIZE: usize = 104;
 const BOTTOM_LINES: usize = 3;
 
+pub const TOP_REGION: Rectangle = Rectangle::new(
+    Point::new(3, 5),
+    Size::new(DISPLAY_WIDTH as u32 - 6, DISPLAY_HEIGHT as u32 - 15),
+);
+/// The box the input line has always been drawn in, it runs past the bottom
+/// of the screen and is clipped.
+pub const BOTTOM_REGION: Rectangle = Rectangle::new(
+    Point::new(3, DISPLAY_HEIGHT as i32 - 16),
+    Size::new(DISPLAY_WIDTH as u32 - 6, DISPLAY_HEIGHT as u32),
+);
+
 type LcdPins = stm32f7xx_hal::fmc_lcd::LcdPins<
     (
         PD14<Alternate<12>>,
@@ -73,6 +87,150 @@ pub type Color = Rgb565;
 
 pub type LcdST7789 = MipiDsiDisplay<Lcd<SubBank1>, LcdResetPin, ST7789>;
 
+const ADDRESS_SETUP_MAX: u32 = 15;
+const DATA_MIN: u32 = 1;
+const DATA_MAX: u32 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingError {
+    /// The read cycle is shorter than the read data phase it has to contain.
+    ReadUnderflow,
+    /// The write cycle is shorter than the write data phase it has to
+    /// contain.
+    WriteUnderflow,
+    /// A duration is too large to be converted to cycles.
+    Overflow,
+    ReadData(u32),
+    ReadAddressSetup(u32),
+    WriteData(u32),
+    WriteAddressSetup(u32),
+}
+
+/// LCD bus timings in nanoseconds, converted to FMC cycles for the current
+/// HCLK when the display is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LcdTiming {
+    /// Rise and fall time of the control signals.
+    pub tedge: u32,
+    /// Write cycle.
+    pub twc: u32,
+    /// Read cycle (frame memory).
+    pub trcfm: u32,
+    /// Write control pulse low duration.
+    pub twrl: u32,
+    /// Read control pulse low duration (frame memory).
+    pub trdlfm: u32,
+}
+
+impl Default for LcdTiming {
+    fn default() -> Self {
+        Self {
+            tedge: 15,
+            twc: 66,
+            trcfm: 450,
+            twrl: 15,
+            trdlfm: 355,
+        }
+    }
+}
+
+impl LcdTiming {
+    pub fn with_write_timing(mut self, twc: u32, twrl: u32) -> Self {
+        self.twc = twc;
+        self.twrl = twrl;
+        self
+    }
+
+    pub fn with_read_timing(mut self, trcfm: u32, trdlfm: u32) -> Self {
+        self.trcfm = trcfm;
+        self.trdlfm = trdlfm;
+        self
+    }
+
+    /// Computes the FMC read and write timings, checking every field fits in
+    /// its register at the given clock speed.
+    pub fn fmc_timings(&self, clocks: &Clocks) -> Result<(Timing, Timing), TimingError> {
+        let hclk_mhz = clocks.hclk().to_MHz();
+        let ns_to_cycles = |ns: u32| {
+            hclk_mhz
+                .checked_mul(ns)
+                .map(|cycles| cycles / 1000)
+                .ok_or(TimingError::Overflow)
+        };
+
+        let check = |cycles: u32, min: u32, max: u32, error: fn(u32) -> TimingError| {
+            if cycles < min || cycles > max {
+                Err(error(cycles))
+            } else {
+                Ok(cycles as u8)
+            }
+        };
+
+        let trdatast = self
+            .trdlfm
+            .checked_add(self.tedge)
+            .ok_or(TimingError::Overflow)?;
+
+        let read_data_cycles = check(
+            ns_to_cycles(trdatast)?,
+            DATA_MIN,
+            DATA_MAX,
+            TimingError::ReadData,
+        )?;
+
+        let read_addrsetup_cycles = check(
+            ns_to_cycles(
+                self.trcfm
+                    .checked_sub(trdatast)
+                    .ok_or(TimingError::ReadUnderflow)?,
+            )?,
+            0,
+            ADDRESS_SETUP_MAX,
+            TimingError::ReadAddressSetup,
+        )?;
+
+        let read_timing = Timing::default()
+            .data(read_data_cycles)
+            .address_setup(read_addrsetup_cycles)
+            .address_hold(0)
+            .bus_turnaround(0)
+            .access_mode(AccessMode::ModeA);
+
+        let twdatast = self
+            .twrl
+            .checked_add(self.tedge)
+            .ok_or(TimingError::Overflow)?;
+
+        let write_data_cycles = check(
+            ns_to_cycles(twdatast)?,
+            DATA_MIN,
+            DATA_MAX,
+            TimingError::WriteData,
+        )?;
+
+        let write_addrsetup_cycles = check(
+            ns_to_cycles(
+                self.twc
+                    .checked_sub(twdatast)
+                    .ok_or(TimingError::WriteUnderflow)?,
+            )?
+            .saturating_sub(1),
+            0,
+            ADDRESS_SETUP_MAX,
+            TimingError::WriteAddressSetup,
+        )?;
+
+        let write_timing = Timing::default()
+            .data(write_data_cycles)
+            .address_setup(write_addrsetup_cycles)
+            .address_hold(0)
+            .bus_turnaround(0)
+            .access_mode(AccessMode::ModeA);
+
+        Ok((read_timing, write_timing))
+    }
+}
+
 pub struct Display {
     pub display: LcdST7789,
     pub top: String<TOP_STRING_SIZE>,
@@ -96,40 +254,9 @@ impl Display {
         mut extd_cmd_pin: LcdExtdCmdPin,
         delay: &mut impl DelayUs<u32>,
         clocks: &Clocks,
-    ) -> Self {
-        let ns_to_cycles = |ns: u32| (clocks.hclk().to_MHz()) * ns / 1000;
-
-        let tedge: u32 = 15;
-        let twc: u32 = 66;
-        let trcfm: u32 = 450;
-        let twrl: u32 = 15;
-        let trdlfm: u32 = 355;
-
-        let trdatast = trdlfm + tedge;
-
-        let read_data_cycles = ns_to_cycles(trdatast);
-
-        let read_addrsetup_cycles = ns_to_cycles(trcfm - trdatast);
-
-        let read_timing = Timing::default()
-            .data(read_data_cycles as u8)
-            .address_setup(read_addrsetup_cycles as u8)
-            .address_hold(0)
-            .bus_turnaround(0)
-            .access_mode(AccessMode::ModeA);
-
-        let twdatast = twrl + tedge;
-
-        let write_data_cycles = ns_to_cycles(twdatast);
-
-        let write_addrsetup_cycles = ns_to_cycles(twc - twdatast) - 1;
-
-        let write_timing = Timing::default()
-            .data(write_data_cycles as u8)
-            .address_setup(write_addrsetup_cycles as u8)
-            .address_hold(0)
-            .bus_turnaround(0)
-            .access_mode(AccessMode::ModeA);
+        timing: &LcdTiming,
+    ) -> Result<Self, TimingError> {
+        let (read_timing, write_timing) = timing.fmc_timings(clocks)?;
 
         power_pin.set_high();
 
@@ -159,7 +286,7 @@ impl Display {
 
         display.clear(Rgb565::BLACK).unwrap();
 
-        Self {
+        Ok(Self {
             display,
             top: String::new(),
             bottom: String::new(),
@@ -169,7 +296,7 @@ impl Display {
             backlight_state: 1,
             _tearing_effect_pin,
             _fmc: fmc,
-        }
+        })
     }
 
     pub fn clear(&mut self, color: Color) {
@@ -247,13 +374,44 @@ impl Display {
         }
     }
 
+    /// Copies the bottom line to the clipboard and clears it.
+    pub fn cut_bottom(&mut self, clipboard: &mut Clipboard, redraw: bool) {
+        clipboard.set(&self.bottom);
+        self.clear_bottom(redraw);
+    }
+
+    pub fn copy_bottom(&self, clipboard: &mut Clipboard) {
+        clipboard.set(&self.bottom);
+    }
+
+    /// Appends the clipboard to the bottom line, returns false if it did not
+    /// fit.
+    pub fn paste_bottom(&mut self, clipboard: &Clipboard, redraw: bool) -> bool {
+        self.write_bottom(clipboard.text(), redraw)
+    }
+
+    /// Applies `Key::Cut`, `Key::Copy` and `Key::Paste` to the bottom line,
+    /// returns `None` for any other key and `Some(false)` if the paste did
+    /// not fit.
+    pub fn handle_clipboard_key(
+        &mut self,
+        key: Key,
+        clipboard: &mut Clipboard,
+        redraw: bool,
+    ) -> Option<bool> {
+        match key {
+            Key::Cut => self.cut_bottom(clipboard, redraw),
+            Key::Copy => self.copy_bottom(clipboard),
+            Key::Paste => return Some(self.paste_bottom(clipboard, redraw)),
+            _ => return None,
+        }
+        Some(true)
+    }
+
     pub fn draw_bottom(&mut self, clear: bool) {
         let character_style = MonoTextStyle::new(&FONT_6X10, Rgb565::GREEN);
 
-        let bottom_bounds = Rectangle::new(
-            Point::new(3, DISPLAY_HEIGHT as i32 - 16),
-            self.display.size() - Size::new(6, 0),
-        );
+        let bottom_bounds = BOTTOM_REGION;
 
         if clear {
             bottom_bounds
@@ -271,7 +429,7 @@ impl Display {
     pub fn draw_top(&mut self, clear: bool) {
         let character_style = MonoTextStyle::new(&FONT_6X10, Rgb565::GREEN);
 
-        let top_bounds = Rectangle::new(Point::new(3, 5), self.display.size() - Size::new(6, 15));
+        let top_bounds = TOP_REGION;
 
         if clear {
             top_bounds
@@ -0,0 +1,139 @@
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+
+use crate::compositor::blend;
+use crate::display::{BG_COLOUR, TEXT_COLOUR};
+
+/// Number of bits used for each pixel's coverage in a glyph atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BitsPerPixel {
+    Two = 2,
+    Four = 4,
+}
+
+impl BitsPerPixel {
+    fn max_level(&self) -> u16 {
+        (1 << *self as u16) - 1
+    }
+}
+
+/// Location and metrics of a single glyph in the atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyph {
+    /// Index of the glyph's first pixel in the atlas, pixels are packed with
+    /// the most significant bits first and rows are not padded.
+    pub offset: u32,
+    pub width: u8,
+    /// Horizontal distance to the next glyph, allowing proportional spacing.
+    pub advance: u8,
+}
+
+/// An anti-aliased font made of glyphs for a contiguous range of characters,
+/// all glyphs are `height` pixels tall.
+#[derive(Debug, Clone, Copy)]
+pub struct GrayFont<'a> {
+    pub data: &'a [u8],
+    pub glyphs: &'a [Glyph],
+    pub first_char: char,
+    pub height: u8,
+    pub bpp: BitsPerPixel,
+    /// Glyph drawn for characters that are not in the font.
+    pub replacement: char,
+}
+
+impl<'a> GrayFont<'a> {
+    fn index(&self, c: char) -> Option<&Glyph> {
+        let index = (c as u32).checked_sub(self.first_char as u32)?;
+        self.glyphs.get(index as usize)
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.index(c).or_else(|| self.index(self.replacement))
+    }
+
+    fn level(&self, pixel: u32) -> u16 {
+        let bpp = self.bpp as u32;
+        let bit = pixel * bpp;
+        let byte = self.data[(bit / 8) as usize];
+        let shift = 8 - bpp - bit % 8;
+        (byte as u16 >> shift) & self.bpp.max_level()
+    }
+
+    /// Width in pixels of `text` when drawn with this font.
+    pub fn text_width(&self, text: &str) -> u32 {
+        text.chars()
+            .filter_map(|c| self.glyph(c))
+            .map(|g| g.advance as u32)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GrayTextStyle<'a> {
+    pub font: &'a GrayFont<'a>,
+    pub text_colour: Rgb565,
+    /// Colour the glyph edges are blended against, this should match what is
+    /// already drawn under the text.
+    pub background_colour: Rgb565,
+}
+
+impl<'a> GrayTextStyle<'a> {
+    pub fn new(font: &'a GrayFont<'a>) -> Self {
+        Self {
+            font,
+            text_colour: TEXT_COLOUR,
+            background_colour: BG_COLOUR,
+        }
+    }
+}
+
+/// A single line of anti-aliased text, `position` is the top left corner.
+pub struct GrayText<'a> {
+    pub text: &'a str,
+    pub position: Point,
+    pub style: GrayTextStyle<'a>,
+}
+
+impl<'a> GrayText<'a> {
+    pub fn new(text: &'a str, position: Point, style: GrayTextStyle<'a>) -> Self {
+        Self {
+            text,
+            position,
+            style,
+        }
+    }
+}
+
+impl Drawable for GrayText<'_> {
+    type Color = Rgb565;
+    type Output = Point;
+
+    /// Returns the position following the last glyph.
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let font = self.style.font;
+        let max_level = font.bpp.max_level();
+        let mut position = self.position;
+
+        for c in self.text.chars() {
+            let glyph = match font.glyph(c) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+
+            let area = Rectangle::new(position, Size::new(glyph.width as u32, font.height as u32));
+            let colours = (0..glyph.width as u32 * font.height as u32).map(|pixel| {
+                let level = font.level(glyph.offset + pixel);
+                let alpha = (level * 255 / max_level) as u8;
+                blend(self.style.text_colour, self.style.background_colour, alpha)
+            });
+            target.fill_contiguous(&area, colours)?;
+
+            position.x += glyph.advance as i32;
+        }
+
+        Ok(position)
+    }
+}
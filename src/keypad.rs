@@ -1,61 +1,930 @@
+use core::fmt;
+use core::str::FromStr;
+
+#[cfg(target_arch = "arm")]
+use cortex_m::peripheral::NVIC;
 use embedded_hal::blocking::delay::DelayUs;
+use heapless::Vec;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+#[cfg(target_arch = "arm")]
 use stm32f7xx_hal::gpio::gpioa::{PAn, PA0, PA1, PA2, PA3, PA4, PA5, PA6, PA7, PA8};
+#[cfg(target_arch = "arm")]
 use stm32f7xx_hal::gpio::{
     gpioc::{PC0, PC1, PC2, PC3, PC4, PC5},
     Floating,
 };
+#[cfg(target_arch = "arm")]
 use stm32f7xx_hal::gpio::{Input, OpenDrain, Output, PullUp};
+#[cfg(target_arch = "arm")]
+use stm32f7xx_hal::pac::{Interrupt, EXTI, SYSCFG};
 
-pub struct KeyPad {
-    alpha_lock: bool,
-    matrix: KeyMatrix,
-    last_state: u16,
+pub mod generic;
+pub mod keymap;
+pub mod recording;
+#[cfg(target_arch = "arm")]
+pub mod scanner;
+pub mod scripted;
+pub mod shortcuts;
+#[cfg(test)]
+mod tests;
+
+pub use generic::GenericMatrix;
+pub use keymap::{KeyLayer, Keymap};
+pub use scripted::{NoDelay, ScriptedMatrix};
+pub use shortcuts::{Shortcut, ShortcutAction, Shortcuts};
+
+/// Milliseconds since an arbitrary point, used to time held keys.
+pub type Instant = fugit::TimerInstantU32<1000>;
+pub type Duration = fugit::MillisDurationU32;
+
+/// A scan can change every switch, plus one shortcut, one repeated key and
+/// a short or long press for every key tracked by long-press detection.
+pub const MAX_EVENTS: usize = 48 + MAX_LONG_PRESS_KEYS;
+
+pub const MAX_SHORTCUTS: usize = 8;
+
+/// Something that can report which switches are pressed, as one byte of
+/// column bits per row.
+///
+/// `KeyMatrix` reads the real switches, `ScriptedMatrix` replays a fixed
+/// sequence so the rest of `KeyPad` can run on the host.
+pub trait ScanSource {
+    fn scan(&mut self, delay: &mut impl DelayUs<u32>) -> [u8; 9];
+}
+
+#[cfg(target_arch = "arm")]
+impl ScanSource for KeyMatrix {
+    fn scan(&mut self, delay: &mut impl DelayUs<u32>) -> [u8; 9] {
+        KeyMatrix::scan(self, delay)
+    }
+}
+
+/// Turns scans from `M` into keys and events, applying ghosting, debouncing,
+/// modifiers and the keymap.
+pub struct KeyPad<M> {
+    matrix: M,
+    modifier_state: ModifierState,
+    last_keys: KeyState,
+    last_scanned: KeyState,
+    /// Key and modifiers each held switch was resolved to when it was
+    /// pressed, indexed by `Switch::index`.
+    pressed_keys: [(Key, Modifiers); 54],
+    debouncer: Debouncer,
+    ghost_policy: GhostPolicy,
+    ghosts: KeyState,
+    last_raw: [u8; 9],
+    injected: Option<KeyState>,
+    auto_repeat: Option<AutoRepeat>,
+    long_press: Option<LongPress>,
+    keymap: Keymap,
+    shortcuts: Shortcuts<MAX_SHORTCUTS>,
 }
 
-impl KeyPad {
-    pub fn new(matrix: KeyMatrix) -> Self {
+impl<M: ScanSource> KeyPad<M> {
+    pub fn new(matrix: M) -> Self {
         Self {
             matrix,
-            alpha_lock: false,
-            last_state: 0,
+            modifier_state: ModifierState::default(),
+            last_keys: KeyState::empty(),
+            last_scanned: KeyState::empty(),
+            pressed_keys: [(Key::NONE, Modifiers::default()); 54],
+            debouncer: Debouncer::default(),
+            ghost_policy: GhostPolicy::Ignore,
+            ghosts: KeyState::empty(),
+            last_raw: [0; 9],
+            injected: None,
+            auto_repeat: Some(AutoRepeat::default()),
+            long_press: Some(LongPress::default()),
+            keymap: Keymap::default(),
+            shortcuts: Shortcuts::new(),
         }
     }
 
+    /// By default every change is reported as soon as it is scanned.
+    pub fn set_debouncer(&mut self, debouncer: Debouncer) {
+        self.debouncer = debouncer;
+    }
+
+    pub fn debouncer_mut(&mut self) -> &mut Debouncer {
+        &mut self.debouncer
+    }
+
+    fn scan(&mut self, delay: &mut impl DelayUs<u32>) -> [u8; 9] {
+        if let Some(state) = self.injected {
+            return state.to_rows();
+        }
+        let mut raw = self.matrix.scan(delay);
+        self.ghosts = match self.ghost_policy {
+            GhostPolicy::Ignore => KeyState::empty(),
+            _ => ghost_switches(raw),
+        };
+        if self.ghost_policy == GhostPolicy::Drop && !self.ghosts.is_empty() {
+            raw = self.last_raw;
+        }
+        self.last_raw = raw;
+        self.debouncer.update(raw)
+    }
+
+    /// Replaces the matrix with `state` until `inject(None)` is called, the
+    /// state still goes through the modifiers, keymap, auto-repeat and
+    /// long-press handling. Used to play back recordings.
+    pub fn inject(&mut self, state: Option<KeyState>) {
+        self.injected = state;
+    }
+
+    /// The state seen by the last `read` or `poll_events` before shortcut
+    /// switches were taken out, for recording.
+    pub fn last_state(&self) -> KeyState {
+        self.last_scanned
+    }
+
+    pub fn set_ghost_policy(&mut self, policy: GhostPolicy) {
+        self.ghost_policy = policy;
+    }
+
+    /// Switches that were part of a ghosting pattern in the last scan, always
+    /// empty with `GhostPolicy::Ignore`.
+    pub fn ghosts(&self) -> KeyState {
+        self.ghosts
+    }
+
+    /// Returns true if the last scan could contain phantom presses.
+    pub fn is_ghosted(&self) -> bool {
+        !self.ghosts.is_empty()
+    }
+
+    pub fn matrix_mut(&mut self) -> &mut M {
+        &mut self.matrix
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+
+    /// Swaps in a new keymap, returning the previous one.
+    pub fn set_keymap(&mut self, keymap: Keymap) -> Keymap {
+        core::mem::replace(&mut self.keymap, keymap)
+    }
+
+    pub fn shortcuts(&self) -> &Shortcuts<MAX_SHORTCUTS> {
+        &self.shortcuts
+    }
+
+    pub fn shortcuts_mut(&mut self) -> &mut Shortcuts<MAX_SHORTCUTS> {
+        &mut self.shortcuts
+    }
+
+    /// Scans the matrix and returns which switches are pressed.
+    pub fn read_state(&mut self, delay: &mut impl DelayUs<u32>) -> KeyState {
+        KeyState::from_rows(self.scan(delay))
+    }
+
+    /// Modifiers that will apply to the next key press, for drawing the
+    /// Shift and Alpha indicators.
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifier_state.modifiers()
+    }
+
+    pub fn modifier_state_mut(&mut self) -> &mut ModifierState {
+        &mut self.modifier_state
+    }
+
+    /// Scans and applies the shortcuts, returning the switches left for
+    /// normal delivery and the event for a completed `ShortcutAction::Event`.
+    fn scan_keys(&mut self, delay: &mut impl DelayUs<u32>) -> (KeyState, Option<KeyEvent>) {
+        let scanned = self.read_state(delay);
+        self.last_scanned = scanned;
+        let (state, fired) = self.shortcuts.update(scanned);
+        let (switch, shortcut) = match fired {
+            Some(fired) => fired,
+            None => return (state, None),
+        };
+
+        // Chord switches pressed in earlier scans were already reported, drop
+        // them without a release and stop their repeat and long press.
+        for held in shortcut.chord.iter() {
+            if !self.last_keys.is_pressed(held) {
+                continue;
+            }
+            self.last_keys.remove(held);
+            if let Some(auto_repeat) = &mut self.auto_repeat {
+                auto_repeat.released(held);
+            }
+            if let Some(long_press) = &mut self.long_press {
+                long_press.released(held);
+            }
+        }
+
+        let modifiers = self.modifier_state.modifiers();
+        // The chord uses up a pending Shift or Alpha like any other key.
+        self.modifier_state.press(Key::NONE);
+        let event = match shortcut.action {
+            ShortcutAction::Callback(callback) => {
+                callback();
+                None
+            }
+            ShortcutAction::Event(id) => Some(KeyEvent::Shortcut {
+                switch,
+                key: self.keymap.key(switch, modifiers.layer()),
+                modifiers,
+                id,
+            }),
+        };
+        (state, event)
+    }
+
+    /// The key and modifiers `switch` was resolved to when it was pressed.
+    fn pressed_key(&self, switch: Switch) -> (Key, Modifiers) {
+        self.pressed_keys[switch.index().unwrap()]
+    }
+
+    /// Updates the modifier state with the switches pressed since the last
+    /// scan, returning each one with its key and the modifiers it used.
+    fn press_modifiers(&mut self, state: KeyState) -> Vec<(Switch, Key, Modifiers), 46> {
+        let mut pressed = Vec::new();
+        let keymap = &self.keymap;
+        self.modifier_state.set_held(
+            state
+                .iter()
+                .any(|switch| keymap.key(switch, KeyLayer::Base) == Key::Shift),
+            state
+                .iter()
+                .any(|switch| keymap.key(switch, KeyLayer::Base) == Key::Alpha),
+        );
+        let is_modifier = |switch: &Switch| {
+            matches!(keymap.key(*switch, KeyLayer::Base), Key::Shift | Key::Alpha)
+        };
+        let new = state.pressed_since(self.last_keys);
+        // Modifiers go first so a key pressed in the same scan uses up their
+        // one-shot instead of leaving it pending.
+        let modifiers_first = new
+            .iter()
+            .filter(is_modifier)
+            .chain(new.iter().filter(|switch| !is_modifier(switch)));
+        for switch in modifiers_first {
+            let modifiers = self.modifier_state.modifiers();
+            let key = keymap.key(switch, modifiers.layer());
+            self.modifier_state.press(key);
+            self.pressed_keys[switch.index().unwrap()] = (key, modifiers);
+            let _ = pressed.push((switch, key, modifiers));
+        }
+        pressed
+    }
+
+    /// Returns the keys held, each resolved when its switch was pressed the
+    /// same way `poll_events` resolves them. Shortcut callbacks are run but
+    /// `ShortcutAction::Event` shortcuts are only reported by `poll_events`.
     pub fn read(&mut self, delay: &mut impl DelayUs<u32>) -> [Key; 46] {
-        let state = self.matrix.scan(delay);
-        let sum: u16 = state.iter().map(|s| *s as u16).sum();
-        let switches = state_to_switches(state);
-        let shift = switches.contains(&Switch::R2C1);
-        let alpha = switches.contains(&Switch::R2C2) || self.alpha_lock;
-        let switch_to_key = if alpha && shift {
-            |sw: &Switch| sw.to_key_alpha(true)
-        } else if alpha {
-            |sw: &Switch| sw.to_key_alpha(false)
-        } else if shift {
-            |sw: &Switch| sw.to_key_shift()
+        let (state, _) = self.scan_keys(delay);
+        self.press_modifiers(state);
+        let mut keys: [Key; 46] = [Key::NONE; 46];
+        for (key, switch) in keys.iter_mut().zip(state.iter()) {
+            *key = self.pressed_key(switch).0;
+        }
+        self.last_keys = state;
+        keys
+    }
+
+    /// Replaces the auto-repeat settings, `None` disables auto-repeat.
+    pub fn set_auto_repeat(&mut self, auto_repeat: Option<AutoRepeat>) {
+        self.auto_repeat = auto_repeat;
+    }
+
+    /// Replaces the long-press settings, `None` disables long-press detection.
+    pub fn set_long_press(&mut self, long_press: Option<LongPress>) {
+        self.long_press = long_press;
+    }
+
+    /// Scans the matrix and returns an event for every switch that changed
+    /// since the previous call, plus the repeat and long-press events due at
+    /// `now`. A completed shortcut is reported first and its switches are
+    /// left out of the other events.
+    pub fn poll_events(
+        &mut self,
+        now: Instant,
+        delay: &mut impl DelayUs<u32>,
+    ) -> Vec<KeyEvent, MAX_EVENTS> {
+        let (state, shortcut) = self.scan_keys(delay);
+        let mut events = Vec::new();
+        if let Some(event) = shortcut {
+            let _ = events.push(event);
+        }
+
+        // At most 46 switches can change in a single scan.
+        for (switch, key, modifiers) in self.press_modifiers(state) {
+            if let Some(auto_repeat) = &mut self.auto_repeat {
+                auto_repeat.pressed(switch, key, modifiers, now);
+            }
+            if let Some(long_press) = &mut self.long_press {
+                long_press.pressed(switch, key, modifiers, now);
+            }
+            let _ = events.push(KeyEvent::Pressed {
+                switch,
+                key,
+                modifiers,
+            });
+        }
+
+        // Released keys report what they were resolved to when pressed, not
+        // what the modifiers give now.
+        for switch in state.released_since(self.last_keys).iter() {
+            let (key, modifiers) = self.pressed_key(switch);
+            if let Some(auto_repeat) = &mut self.auto_repeat {
+                auto_repeat.released(switch);
+            }
+            let _ = events.push(KeyEvent::Released {
+                switch,
+                key,
+                modifiers,
+            });
+            if let Some(long_press) = &mut self.long_press {
+                if long_press.released(switch) {
+                    let _ = events.push(KeyEvent::ShortPressed {
+                        switch,
+                        key,
+                        modifiers,
+                    });
+                }
+            }
+        }
+
+        if let Some((switch, key, modifiers)) = self.auto_repeat.as_mut().and_then(|a| a.poll(now))
+        {
+            let _ = events.push(KeyEvent::Repeated {
+                switch,
+                key,
+                modifiers,
+            });
+        }
+
+        if let Some(long_press) = &mut self.long_press {
+            long_press.poll(now, |switch, key, modifiers| {
+                let _ = events.push(KeyEvent::LongPressed {
+                    switch,
+                    key,
+                    modifiers,
+                });
+            });
+        }
+
+        self.last_keys = state;
+        events
+    }
+}
+
+#[cfg(target_arch = "arm")]
+impl KeyPad<KeyMatrix> {
+    /// Sleeps with `WFE` until a key is pressed, the MCU can be put in stop
+    /// mode beforehand by setting `SLEEPDEEP` and the `PWR` registers.
+    pub fn wait_for_key(&mut self) {
+        self.matrix.enable_wakeup(WakeMode::Event);
+        while !self.matrix.any_pressed() {
+            cortex_m::asm::wfe();
+        }
+        self.matrix.disable_wakeup();
+    }
+}
+
+/// Repeats the most recently pressed key while it is held down, after an
+/// initial delay.
+pub struct AutoRepeat {
+    pub delay: Duration,
+    /// Time between repeats once the initial delay has passed.
+    pub interval: Duration,
+    /// Decides which keys repeat.
+    pub policy: fn(Key) -> bool,
+    /// The held switch, what it was resolved to and when it next repeats.
+    held: Option<(Switch, Key, Modifiers, Instant)>,
+}
+
+impl AutoRepeat {
+    pub fn new(delay: Duration, interval: Duration) -> Self {
+        Self {
+            delay,
+            interval,
+            policy: default_repeat_policy,
+            held: None,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: fn(Key) -> bool) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn pressed(&mut self, switch: Switch, key: Key, modifiers: Modifiers, now: Instant) {
+        self.held = if (self.policy)(key) {
+            Some((switch, key, modifiers, now + self.delay))
         } else {
-            |sw: &Switch| sw.to_key()
+            None
         };
-        let iter = switches.iter().map(switch_to_key);
-        let mut keys: [Key; 46] = [Key::NONE; 46];
+    }
+
+    fn released(&mut self, switch: Switch) {
+        if matches!(self.held, Some((held, ..)) if held == switch) {
+            self.held = None;
+        }
+    }
+
+    fn poll(&mut self, now: Instant) -> Option<(Switch, Key, Modifiers)> {
+        let (switch, key, modifiers, next) = self.held?;
+        if now >= next {
+            self.held = Some((switch, key, modifiers, now + self.interval));
+            Some((switch, key, modifiers))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for AutoRepeat {
+    fn default() -> Self {
+        Self::new(Duration::millis(500), Duration::millis(100))
+    }
+}
+
+/// Every switch can be tracked at once, so no key loses its short or long
+/// press.
+pub const MAX_LONG_PRESS_KEYS: usize = 46;
+
+/// Tells short and long presses apart for the keys selected by `policy`.
+///
+/// A `LongPressed` event is sent once a key has been held for `threshold`,
+/// releasing the key before that sends a `ShortPressed` event instead. The
+/// usual `Pressed` and `Released` events are still sent for these keys.
+pub struct LongPress {
+    pub threshold: Duration,
+    pub policy: fn(Key) -> bool,
+    held: Vec<(Switch, Key, Modifiers, Instant), MAX_LONG_PRESS_KEYS>,
+}
+
+impl LongPress {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            policy: default_long_press_policy,
+            held: Vec::new(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: fn(Key) -> bool) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn pressed(&mut self, switch: Switch, key: Key, modifiers: Modifiers, now: Instant) {
+        if (self.policy)(key) {
+            let _ = self
+                .held
+                .push((switch, key, modifiers, now + self.threshold));
+        }
+    }
+
+    /// Returns true if the switch was released before its threshold.
+    fn released(&mut self, switch: Switch) -> bool {
+        match self.held.iter().position(|(held, ..)| *held == switch) {
+            Some(index) => {
+                self.held.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Calls `f` for every switch that reached its threshold, those switches
+    /// are then no longer tracked until they are pressed again.
+    fn poll(&mut self, now: Instant, mut f: impl FnMut(Switch, Key, Modifiers)) {
         let mut index = 0;
-        iter.for_each(|k| {
-            keys[index] = k;
-            index += 1;
-        });
-        if sum != self.last_state {
-            self.last_state = sum;
-            if keys.contains(&Key::AlphaLock) {
+        while index < self.held.len() {
+            let (switch, key, modifiers, deadline) = self.held[index];
+            if now >= deadline {
+                self.held.swap_remove(index);
+                f(switch, key, modifiers);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+impl Default for LongPress {
+    fn default() -> Self {
+        Self::new(Duration::millis(1000))
+    }
+}
+
+/// `Back` (to exit apps), `Home` and `Power` can be long pressed.
+pub fn default_long_press_policy(key: Key) -> bool {
+    matches!(key, Key::Back | Key::Home | Key::Power)
+}
+
+/// Navigation keys and the deletion keys repeat, nothing else does.
+pub fn default_repeat_policy(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Left | Key::Up | Key::Down | Key::Right | Key::Delete | Key::Clear
+    )
+}
+
+/// What `KeyPad` does with scans that contain a ghosting pattern.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GhostPolicy {
+    /// Do not look for ghosting.
+    Ignore,
+    /// Use the scan as is but flag the switches involved, see
+    /// `KeyPad::ghosts`.
+    Report,
+    /// Flag the switches involved and keep the previous scan instead.
+    Drop,
+}
+
+/// Returned when parsing a `Key` or `Switch` from an unknown name.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ParseNameError;
+
+impl fmt::Display for ParseNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unknown key name")
+    }
+}
+
+/// Pressed switches, one bit per switch at `row * 6 + column`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyState(pub u64);
+
+impl KeyState {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Builds the state from the row bytes returned by `KeyMatrix::scan`.
+    /// Positions without a `Switch` are dropped.
+    pub fn from_rows(rows: [u8; 9]) -> Self {
+        let bits = rows
+            .iter()
+            .zip(DEFAULT_ROW_MASKS)
+            .enumerate()
+            .fold(0u64, |bits, (n, (row, mask))| {
+                bits | (((*row & mask) as u64) << (n * 6))
+            });
+        Self(bits)
+    }
+
+    pub fn to_rows(&self) -> [u8; 9] {
+        let mut rows = [0; 9];
+        for (n, row) in rows.iter_mut().enumerate() {
+            *row = (self.0 >> (n * 6)) as u8 & 0b111111;
+        }
+        rows
+    }
+
+    fn bit(switch: Switch) -> u64 {
+        switch.index().map_or(0, |index| 1 << index)
+    }
+
+    pub fn is_pressed(&self, switch: Switch) -> bool {
+        self.0 & Self::bit(switch) != 0
+    }
+
+    pub fn insert(&mut self, switch: Switch) {
+        self.0 |= Self::bit(switch);
+    }
+
+    pub fn remove(&mut self, switch: Switch) {
+        self.0 &= !Self::bit(switch);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn count(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// Returns true if every switch in `other` is pressed in `self`.
+    pub fn contains(&self, other: KeyState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Switches whose state differs between `self` and `previous`.
+    pub fn changed_since(&self, previous: KeyState) -> KeyState {
+        Self(self.0 ^ previous.0)
+    }
+
+    pub fn pressed_since(&self, previous: KeyState) -> KeyState {
+        Self(self.0 & !previous.0)
+    }
+
+    pub fn released_since(&self, previous: KeyState) -> KeyState {
+        Self(previous.0 & !self.0)
+    }
+
+    /// The pressed switches followed by `Switch::NONE`.
+    pub fn to_switches(&self) -> [Switch; 46] {
+        let mut switches = [Switch::NONE; 46];
+        for (slot, switch) in switches.iter_mut().zip(self.iter()) {
+            *slot = switch;
+        }
+        switches
+    }
+
+    /// Iterates over the pressed switches in row then column order.
+    pub fn iter(&self) -> KeyStateIter {
+        KeyStateIter(self.0)
+    }
+}
+
+impl FromIterator<Switch> for KeyState {
+    fn from_iter<T: IntoIterator<Item = Switch>>(iter: T) -> Self {
+        let mut state = Self::empty();
+        for switch in iter {
+            state.insert(switch);
+        }
+        state
+    }
+}
+
+impl IntoIterator for KeyState {
+    type Item = Switch;
+    type IntoIter = KeyStateIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct KeyStateIter(u64);
+
+impl Iterator for KeyStateIter {
+    type Item = Switch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.0 != 0 {
+            let index = self.0.trailing_zeros();
+            self.0 &= self.0 - 1;
+            if let Some(switch) = Switch::from_index(index as usize) {
+                return Some(switch);
+            }
+        }
+        None
+    }
+}
+
+/// Filters scanned row states so a switch only changes once it has read the
+/// same value for a number of consecutive scans.
+pub struct Debouncer {
+    stable: [u8; 9],
+    /// Switches that currently differ from `stable` and are being counted.
+    pending: [u8; 9],
+    counters: [[u8; 6]; 9],
+    samples: [[u8; 6]; 9],
+}
+
+impl Debouncer {
+    /// `samples` is the number of consecutive scans a switch must stay in its
+    /// new state before the change is accepted, 1 disables debouncing.
+    pub fn new(samples: u8) -> Self {
+        Self {
+            stable: [0; 9],
+            pending: [0; 9],
+            counters: [[0; 6]; 9],
+            samples: [[samples.max(1); 6]; 9],
+        }
+    }
+
+    /// Converts a time window into a sample count for a scanner that runs
+    /// every `scan_period_us` microseconds.
+    pub fn window_to_samples(window_us: u32, scan_period_us: u32) -> u8 {
+        let scan_period_us = scan_period_us.max(1);
+        let samples = window_us.div_ceil(scan_period_us);
+        samples.clamp(1, u8::MAX as u32) as u8
+    }
+
+    pub fn with_window(window_us: u32, scan_period_us: u32) -> Self {
+        Self::new(Self::window_to_samples(window_us, scan_period_us))
+    }
+
+    pub fn set_samples(&mut self, switch: Switch, samples: u8) {
+        if let Some((row, col)) = switch.position() {
+            self.samples[row][col] = samples.max(1);
+        }
+    }
+
+    pub fn set_window(&mut self, switch: Switch, window_us: u32, scan_period_us: u32) {
+        self.set_samples(switch, Self::window_to_samples(window_us, scan_period_us));
+    }
+
+    /// Feeds a raw scan in and returns the debounced state.
+    pub fn update(&mut self, raw: [u8; 9]) -> [u8; 9] {
+        for (row, raw) in raw.iter().enumerate() {
+            let changed = raw ^ self.stable[row];
+            // Reset the counters of switches that went back to their stable
+            // state before the change was accepted.
+            let bounced = self.pending[row] & !changed;
+            if changed == 0 && bounced == 0 {
+                continue;
+            }
+            for col in 0..6 {
+                let bit = 1 << col;
+                if bounced & bit != 0 {
+                    self.counters[row][col] = 0;
+                } else if changed & bit != 0 {
+                    self.counters[row][col] += 1;
+                    if self.counters[row][col] >= self.samples[row][col] {
+                        self.stable[row] ^= bit;
+                        self.counters[row][col] = 0;
+                    }
+                }
+            }
+            self.pending[row] = raw ^ self.stable[row];
+        }
+        self.stable
+    }
+}
+
+impl Default for Debouncer {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+/// Tracks Shift and Alpha the way the calculator does.
+///
+/// Tapping Shift or Alpha applies it to the next key only, tapping Alpha
+/// twice or pressing Shift then Alpha (`Key::AlphaLock`) locks Alpha until
+/// Alpha is pressed again. Holding Shift or Alpha applies it to every key
+/// pressed while it is held.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ModifierState {
+    shift: bool,
+    alpha: bool,
+    alpha_lock: bool,
+    shift_held: bool,
+    alpha_held: bool,
+}
+
+impl ModifierState {
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.shift || self.shift_held,
+            alpha: self.alpha || self.alpha_held,
+            alpha_lock: self.alpha_lock,
+        }
+    }
+
+    pub fn set_held(&mut self, shift: bool, alpha: bool) {
+        self.shift_held = shift;
+        self.alpha_held = alpha;
+    }
+
+    /// Advances the state with a newly pressed key, resolved using the
+    /// current `modifiers`.
+    pub fn press(&mut self, key: Key) {
+        match key {
+            Key::Shift => self.shift = !self.shift,
+            Key::Alpha => {
+                if self.alpha_lock {
+                    self.alpha_lock = false;
+                } else if self.alpha {
+                    self.alpha_lock = true;
+                    self.alpha = false;
+                } else {
+                    self.alpha = true;
+                }
+            }
+            Key::AlphaLock => {
                 self.alpha_lock = !self.alpha_lock;
+                self.alpha = false;
+                self.shift = false;
+            }
+            _ => {
+                self.shift = false;
+                self.alpha = false;
             }
         }
-        keys
+    }
+
+    /// Clears every modifier, including Alpha-lock.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Modifier keys that were active when a key was resolved.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alpha: bool,
+    pub alpha_lock: bool,
+}
+
+impl Modifiers {
+    pub fn layer(&self) -> KeyLayer {
+        KeyLayer::from(*self)
+    }
+
+    /// Letters are uppercase when Shift is combined with Alpha or Alpha-lock.
+    pub fn is_uppercase(&self) -> bool {
+        self.shift && (self.alpha || self.alpha_lock)
+    }
+
+    /// Resolves the key a switch produces with these modifiers using the
+    /// default keymap.
+    pub fn key(&self, switch: Switch) -> Key {
+        self.layer().default_key(switch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum KeyEvent {
+    Pressed {
+        switch: Switch,
+        key: Key,
+        modifiers: Modifiers,
+    },
+    Released {
+        switch: Switch,
+        key: Key,
+        modifiers: Modifiers,
+    },
+    /// A key that is still held, sent by auto-repeat.
+    Repeated {
+        switch: Switch,
+        key: Key,
+        modifiers: Modifiers,
+    },
+    /// A key watched for long presses that was released before the
+    /// threshold.
+    ShortPressed {
+        switch: Switch,
+        key: Key,
+        modifiers: Modifiers,
+    },
+    /// A key watched for long presses that was held past the threshold.
+    LongPressed {
+        switch: Switch,
+        key: Key,
+        modifiers: Modifiers,
+    },
+    /// A chord bound with `ShortcutAction::Event(id)` was completed by
+    /// pressing `switch`.
+    Shortcut {
+        switch: Switch,
+        key: Key,
+        modifiers: Modifiers,
+        id: u8,
+    },
+}
+
+impl KeyEvent {
+    pub fn switch(&self) -> Switch {
+        match self {
+            Self::Pressed { switch, .. }
+            | Self::Released { switch, .. }
+            | Self::Repeated { switch, .. }
+            | Self::ShortPressed { switch, .. }
+            | Self::LongPressed { switch, .. }
+            | Self::Shortcut { switch, .. } => *switch,
+        }
+    }
+
+    pub fn key(&self) -> Key {
+        match self {
+            Self::Pressed { key, .. }
+            | Self::Released { key, .. }
+            | Self::Repeated { key, .. }
+            | Self::ShortPressed { key, .. }
+            | Self::LongPressed { key, .. }
+            | Self::Shortcut { key, .. } => *key,
+        }
+    }
+
+    /// The character this event types, taking the case into account.
+    pub fn to_char(&self) -> char {
+        self.key().to_char(self.modifiers())
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        match self {
+            Self::Pressed { modifiers, .. }
+            | Self::Released { modifiers, .. }
+            | Self::Repeated { modifiers, .. }
+            | Self::ShortPressed { modifiers, .. }
+            | Self::LongPressed { modifiers, .. }
+            | Self::Shortcut { modifiers, .. } => *modifiers,
+        }
     }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Switch {
     R0C1 = 0x01,
@@ -107,7 +976,100 @@ pub enum Switch {
     NONE = 0xff,
 }
 
+impl fmt::Display for Switch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Switch {
+    type Err = ParseNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=u8::MAX)
+            .filter_map(Switch::from_u8)
+            .find(|switch| switch.name() == s)
+            .ok_or(ParseNameError)
+    }
+}
+
 impl Switch {
+    /// Stable name of the switch, as used by `Display` and `FromStr`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::R0C1 => "R0C1",
+            Self::R0C2 => "R0C2",
+            Self::R0C3 => "R0C3",
+            Self::R0C4 => "R0C4",
+            Self::R0C5 => "R0C5",
+            Self::R0C6 => "R0C6",
+            Self::R1C1 => "R1C1",
+            Self::R1C3 => "R1C3",
+            Self::R2C1 => "R2C1",
+            Self::R2C2 => "R2C2",
+            Self::R2C3 => "R2C3",
+            Self::R2C4 => "R2C4",
+            Self::R2C5 => "R2C5",
+            Self::R2C6 => "R2C6",
+            Self::R3C1 => "R3C1",
+            Self::R3C2 => "R3C2",
+            Self::R3C3 => "R3C3",
+            Self::R3C4 => "R3C4",
+            Self::R3C5 => "R3C5",
+            Self::R3C6 => "R3C6",
+            Self::R4C1 => "R4C1",
+            Self::R4C2 => "R4C2",
+            Self::R4C3 => "R4C3",
+            Self::R4C4 => "R4C4",
+            Self::R4C5 => "R4C5",
+            Self::R4C6 => "R4C6",
+            Self::R5C1 => "R5C1",
+            Self::R5C2 => "R5C2",
+            Self::R5C3 => "R5C3",
+            Self::R5C4 => "R5C4",
+            Self::R5C5 => "R5C5",
+            Self::R6C1 => "R6C1",
+            Self::R6C2 => "R6C2",
+            Self::R6C3 => "R6C3",
+            Self::R6C4 => "R6C4",
+            Self::R6C5 => "R6C5",
+            Self::R7C1 => "R7C1",
+            Self::R7C2 => "R7C2",
+            Self::R7C3 => "R7C3",
+            Self::R7C4 => "R7C4",
+            Self::R7C5 => "R7C5",
+            Self::R8C1 => "R8C1",
+            Self::R8C2 => "R8C2",
+            Self::R8C3 => "R8C3",
+            Self::R8C4 => "R8C4",
+            Self::R8C5 => "R8C5",
+            Self::NONE => "NONE",
+        }
+    }
+
+    /// Row and column (starting at 0) of the switch in the matrix.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::NONE => None,
+            _ => {
+                let value = *self as u8;
+                Some(((value >> 4) as usize, (value & 0xf) as usize - 1))
+            }
+        }
+    }
+
+    /// Bit index of the switch in a `KeyState`.
+    pub fn index(&self) -> Option<usize> {
+        self.position().map(|(row, col)| row * 6 + col)
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        if index >= 9 * 6 {
+            return None;
+        }
+        Self::from_u8(((index / 6) << 4) as u8 + (index % 6) as u8 + 1)
+    }
+
     pub fn to_key(&self) -> Key {
         match self {
             Self::R0C1 => Key::Left,
@@ -227,106 +1189,365 @@ impl Switch {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// The values are saved in keymaps, so existing keys must keep theirs and
+/// new keys must take unused ones.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Key {
-    Left,
-    Up,
-    Down,
-    Right,
-    Ok,
-    Back,
-    Home,
-    Power,
-    Shift,
-    Alpha,
-    AlphaLock,
-    XNT,
-    Var,
-    Toolbox,
-    Delete,
-    Euler,
-    Ln,
-    Log,
-    Imaginary,
-    Comma,
-    Pow,
-    Sin,
-    Cos,
-    Tan,
-    Pi,
-    Sqrt,
-    Square,
-    Seven,
-    Eight,
-    Nine,
-    LBracket,
-    RBracket,
-    Four,
-    Five,
-    Six,
-    Multiply,
-    Divide,
-    One,
-    Two,
-    Three,
-    Add,
-    Subtract,
-    Zero,
-    Dot,
-    EE,
-    Ans,
-    EXE,
-    Cut,
-    Copy,
-    Paste,
-    Clear,
-    RSqBracket,
-    LSqBracket,
-    RCurlyBrace,
-    LCurlyBrace,
-    Underscore,
-    Sto,
-    ASin,
-    ACos,
-    ATan,
-    Equals,
-    Less,
-    Greater,
-    Colon,
-    SemiColon,
-    Quote,
-    Percent,
-    A,
-    B,
-    C,
-    D,
-    E,
-    F,
-    G,
-    H,
-    I,
-    J,
-    K,
-    L,
-    M,
-    N,
-    O,
-    P,
-    Q,
-    R,
-    S,
-    T,
-    U,
-    V,
-    W,
-    X,
-    Y,
-    Z,
-    Space,
-    Question,
-    Exclamation,
-    NONE,
+    Left = 0,
+    Up = 1,
+    Down = 2,
+    Right = 3,
+    Ok = 4,
+    Back = 5,
+    Home = 6,
+    Power = 7,
+    Shift = 8,
+    Alpha = 9,
+    AlphaLock = 10,
+    XNT = 11,
+    Var = 12,
+    Toolbox = 13,
+    Delete = 14,
+    Euler = 15,
+    Ln = 16,
+    Log = 17,
+    Imaginary = 18,
+    Comma = 19,
+    Pow = 20,
+    Sin = 21,
+    Cos = 22,
+    Tan = 23,
+    Pi = 24,
+    Sqrt = 25,
+    Square = 26,
+    Seven = 27,
+    Eight = 28,
+    Nine = 29,
+    LBracket = 30,
+    RBracket = 31,
+    Four = 32,
+    Five = 33,
+    Six = 34,
+    Multiply = 35,
+    Divide = 36,
+    One = 37,
+    Two = 38,
+    Three = 39,
+    Add = 40,
+    Subtract = 41,
+    Zero = 42,
+    Dot = 43,
+    EE = 44,
+    Ans = 45,
+    EXE = 46,
+    Cut = 47,
+    Copy = 48,
+    Paste = 49,
+    Clear = 50,
+    RSqBracket = 51,
+    LSqBracket = 52,
+    RCurlyBrace = 53,
+    LCurlyBrace = 54,
+    Underscore = 55,
+    Sto = 56,
+    ASin = 57,
+    ACos = 58,
+    ATan = 59,
+    Equals = 60,
+    Less = 61,
+    Greater = 62,
+    Colon = 63,
+    SemiColon = 64,
+    Quote = 65,
+    Percent = 66,
+    A = 67,
+    B = 68,
+    C = 69,
+    D = 70,
+    E = 71,
+    F = 72,
+    G = 73,
+    H = 74,
+    I = 75,
+    J = 76,
+    K = 77,
+    L = 78,
+    M = 79,
+    N = 80,
+    O = 81,
+    P = 82,
+    Q = 83,
+    R = 84,
+    S = 85,
+    T = 86,
+    U = 87,
+    V = 88,
+    W = 89,
+    X = 90,
+    Y = 91,
+    Z = 92,
+    Space = 93,
+    Question = 94,
+    Exclamation = 95,
+    NONE = 96,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Key {
+    type Err = ParseNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=Key::NONE as u8)
+            .filter_map(Key::from_u8)
+            .find(|key| key.name() == s)
+            .ok_or(ParseNameError)
+    }
+}
+
+const UPPERCASE_LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+impl Key {
+    /// Stable name of the key, as used by `Display` and `FromStr`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Left => "Left",
+            Self::Up => "Up",
+            Self::Down => "Down",
+            Self::Right => "Right",
+            Self::Ok => "Ok",
+            Self::Back => "Back",
+            Self::Home => "Home",
+            Self::Power => "Power",
+            Self::Shift => "Shift",
+            Self::Alpha => "Alpha",
+            Self::AlphaLock => "AlphaLock",
+            Self::XNT => "XNT",
+            Self::Var => "Var",
+            Self::Toolbox => "Toolbox",
+            Self::Delete => "Delete",
+            Self::Euler => "Euler",
+            Self::Ln => "Ln",
+            Self::Log => "Log",
+            Self::Imaginary => "Imaginary",
+            Self::Comma => "Comma",
+            Self::Pow => "Pow",
+            Self::Sin => "Sin",
+            Self::Cos => "Cos",
+            Self::Tan => "Tan",
+            Self::Pi => "Pi",
+            Self::Sqrt => "Sqrt",
+            Self::Square => "Square",
+            Self::Seven => "Seven",
+            Self::Eight => "Eight",
+            Self::Nine => "Nine",
+            Self::LBracket => "LBracket",
+            Self::RBracket => "RBracket",
+            Self::Four => "Four",
+            Self::Five => "Five",
+            Self::Six => "Six",
+            Self::Multiply => "Multiply",
+            Self::Divide => "Divide",
+            Self::One => "One",
+            Self::Two => "Two",
+            Self::Three => "Three",
+            Self::Add => "Add",
+            Self::Subtract => "Subtract",
+            Self::Zero => "Zero",
+            Self::Dot => "Dot",
+            Self::EE => "EE",
+            Self::Ans => "Ans",
+            Self::EXE => "EXE",
+            Self::Cut => "Cut",
+            Self::Copy => "Copy",
+            Self::Paste => "Paste",
+            Self::Clear => "Clear",
+            Self::RSqBracket => "RSqBracket",
+            Self::LSqBracket => "LSqBracket",
+            Self::RCurlyBrace => "RCurlyBrace",
+            Self::LCurlyBrace => "LCurlyBrace",
+            Self::Underscore => "Underscore",
+            Self::Sto => "Sto",
+            Self::ASin => "ASin",
+            Self::ACos => "ACos",
+            Self::ATan => "ATan",
+            Self::Equals => "Equals",
+            Self::Less => "Less",
+            Self::Greater => "Greater",
+            Self::Colon => "Colon",
+            Self::SemiColon => "SemiColon",
+            Self::Quote => "Quote",
+            Self::Percent => "Percent",
+            Self::A => "A",
+            Self::B => "B",
+            Self::C => "C",
+            Self::D => "D",
+            Self::E => "E",
+            Self::F => "F",
+            Self::G => "G",
+            Self::H => "H",
+            Self::I => "I",
+            Self::J => "J",
+            Self::K => "K",
+            Self::L => "L",
+            Self::M => "M",
+            Self::N => "N",
+            Self::O => "O",
+            Self::P => "P",
+            Self::Q => "Q",
+            Self::R => "R",
+            Self::S => "S",
+            Self::T => "T",
+            Self::U => "U",
+            Self::V => "V",
+            Self::W => "W",
+            Self::X => "X",
+            Self::Y => "Y",
+            Self::Z => "Z",
+            Self::Space => "Space",
+            Self::Question => "Question",
+            Self::Exclamation => "Exclamation",
+            Self::NONE => "NONE",
+        }
+    }
+
+    pub fn is_letter(&self) -> bool {
+        (Key::A as u8..=Key::Z as u8).contains(&(*self as u8))
+    }
+
+    /// Text the key inserts into an editor, empty for keys that do not
+    /// insert anything. Letters are lowercase, see `Key::token_for`.
+    pub fn token(&self) -> &'static str {
+        match self {
+            Key::Euler => "ℯ^(",
+            Key::Imaginary => "𝐢",
+            Key::Pi => "π",
+            Key::Sqrt => "√(",
+            Key::Multiply => "×",
+            Key::EE => "ᴇ",
+            Key::Sto => "→",
+            Key::ASin => "arcsin(",
+            Key::ACos => "arccos(",
+            Key::ATan => "arctan(",
+            _ => self.token_ascii(),
+        }
+    }
+
+    /// Like `Key::token` but only uses ASCII characters.
+    pub fn token_ascii(&self) -> &'static str {
+        match self {
+            Key::XNT => "x",
+            Key::Euler => "exp(",
+            Key::Ln => "ln(",
+            Key::Log => "log(",
+            Key::Imaginary => "i",
+            Key::Comma => ",",
+            Key::Pow => "^",
+            Key::Sin => "sin(",
+            Key::Cos => "cos(",
+            Key::Tan => "tan(",
+            Key::Pi => "pi",
+            Key::Sqrt => "sqrt(",
+            Key::Square => "^2",
+            Key::Seven => "7",
+            Key::Eight => "8",
+            Key::Nine => "9",
+            Key::LBracket => "(",
+            Key::RBracket => ")",
+            Key::Four => "4",
+            Key::Five => "5",
+            Key::Six => "6",
+            Key::Multiply => "*",
+            Key::Divide => "/",
+            Key::One => "1",
+            Key::Two => "2",
+            Key::Three => "3",
+            Key::Add => "+",
+            Key::Subtract => "-",
+            Key::Zero => "0",
+            Key::Dot => ".",
+            Key::EE => "E",
+            Key::Ans => "ans",
+            Key::EXE => "\n",
+            Key::RSqBracket => "[",
+            Key::LSqBracket => "]",
+            Key::RCurlyBrace => "{",
+            Key::LCurlyBrace => "}",
+            Key::Underscore => "_",
+            Key::Sto => "->",
+            Key::ASin => "asin(",
+            Key::ACos => "acos(",
+            Key::ATan => "atan(",
+            Key::Equals => "=",
+            Key::Less => "<",
+            Key::Greater => ">",
+            Key::Colon => ":",
+            Key::SemiColon => ";",
+            Key::Quote => "\"",
+            Key::Percent => "%",
+            Key::A => "a",
+            Key::B => "b",
+            Key::C => "c",
+            Key::D => "d",
+            Key::E => "e",
+            Key::F => "f",
+            Key::G => "g",
+            Key::H => "h",
+            Key::I => "i",
+            Key::J => "j",
+            Key::K => "k",
+            Key::L => "l",
+            Key::M => "m",
+            Key::N => "n",
+            Key::O => "o",
+            Key::P => "p",
+            Key::Q => "q",
+            Key::R => "r",
+            Key::S => "s",
+            Key::T => "t",
+            Key::U => "u",
+            Key::V => "v",
+            Key::W => "w",
+            Key::X => "x",
+            Key::Y => "y",
+            Key::Z => "z",
+            Key::Space => " ",
+            Key::Question => "?",
+            Key::Exclamation => "!",
+            _ => "",
+        }
+    }
+
+    /// Like `Key::token`, but letters are uppercase when `modifiers` ask for
+    /// it.
+    pub fn token_for(&self, modifiers: Modifiers, ascii: bool) -> &'static str {
+        if self.is_letter() && modifiers.is_uppercase() {
+            let index = (*self as u8 - Key::A as u8) as usize;
+            return &UPPERCASE_LETTERS[index..index + 1];
+        }
+        if ascii {
+            self.token_ascii()
+        } else {
+            self.token()
+        }
+    }
+
+    /// Like `char::from`, but letters typed with Shift and Alpha (or
+    /// Alpha-lock) active are uppercase.
+    pub fn to_char(&self, modifiers: Modifiers) -> char {
+        let c = char::from(*self);
+        if self.is_letter() && modifiers.is_uppercase() {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        }
+    }
 }
 
 impl From<Key> for char {
@@ -401,6 +1622,7 @@ impl From<Key> for char {
     }
 }
 
+#[cfg(target_arch = "arm")]
 struct KeyColumns(
     PC0<Input<PullUp>>,
     PC1<Input<PullUp>>,
@@ -410,6 +1632,7 @@ struct KeyColumns(
     PC5<Input<PullUp>>,
 );
 
+#[cfg(target_arch = "arm")]
 impl KeyColumns {
     fn read(&self) -> u8 {
         // SAFETY: Atomic read with no side effects
@@ -418,13 +1641,57 @@ impl KeyColumns {
     }
 }
 
+/// The NumWorks key matrix, with the rows on PA0 to PA8 and the columns on
+/// PC0 to PC5. Other wirings can use `GenericMatrix`.
+#[cfg(target_arch = "arm")]
 pub struct KeyMatrix {
     rows: [PAn<Output<OpenDrain>>; 9],
     columns: KeyColumns,
+    row_masks: [u8; 9],
 }
 
+/// Columns that have a switch in each row of the NumWorks keypad.
+pub const DEFAULT_ROW_MASKS: [u8; 9] = [
+    0b111111, 0b000101, 0b111111, 0b111111, 0b111111, 0b011111, 0b011111, 0b011111, 0b011111,
+];
+
+/// Time for the columns to settle after driving a row low.
+const SETTLE_US: u32 = 10;
+
+/// The columns are on PC0 to PC5, so they use EXTI lines 0 to 5.
+#[cfg(target_arch = "arm")]
+const COLUMN_MASK: u32 = 0b111111;
+
+/// EXTI port selection value for GPIOC.
+#[cfg(target_arch = "arm")]
+const EXTI_PORT_C: u32 = 0b0010;
+
+#[cfg(target_arch = "arm")]
+const COLUMN_INTERRUPTS: [Interrupt; 6] = [
+    Interrupt::EXTI0,
+    Interrupt::EXTI1,
+    Interrupt::EXTI2,
+    Interrupt::EXTI3,
+    Interrupt::EXTI4,
+    Interrupt::EXTI9_5,
+];
+
+/// How a key press wakes the MCU once `KeyMatrix::enable_wakeup` is called.
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WakeMode {
+    /// Generate an EXTI event, which wakes the MCU from `WFE` or stop mode
+    /// without needing an interrupt handler.
+    Event,
+    /// Raise the EXTI0 to EXTI4 and EXTI9_5 interrupts, which wakes the MCU
+    /// from `WFI`. The handlers must call `KeyMatrix::clear_wakeup`.
+    Interrupt,
+}
+
+#[cfg(target_arch = "arm")]
 type MODE = Input<Floating>;
 
+#[cfg(target_arch = "arm")]
 impl KeyMatrix {
     pub fn new(
         pa0: PA0<MODE>,
@@ -484,54 +1751,143 @@ impl KeyMatrix {
             pc5.into_pull_up_input(),
         );
 
-        Self { rows, columns }
+        Self {
+            rows,
+            columns,
+            row_masks: DEFAULT_ROW_MASKS,
+        }
+    }
+
+    /// Sets which columns of each row have a switch, for board revisions
+    /// that leave out or add keys.
+    pub fn with_row_masks(mut self, row_masks: [u8; 9]) -> Self {
+        self.row_masks = row_masks;
+        self
+    }
+
+    pub fn set_row_masks(&mut self, row_masks: [u8; 9]) {
+        self.row_masks = row_masks;
+    }
+
+    pub fn row_masks(&self) -> [u8; 9] {
+        self.row_masks
     }
 
     pub fn scan(&mut self, delay: &mut impl DelayUs<u32>) -> [u8; 9] {
-        let mut state = [
-            0b111111, 0b000101, 0b111111, 0b111111, 0b111111, 0b011111, 0b011111, 0b011111,
-            0b011111,
-        ];
+        let mut state = self.row_masks;
 
         for (row_pin, row_state) in self.rows.iter_mut().zip(&mut state) {
             row_pin.set_low();
-            delay.delay_us(10);
+            delay.delay_us(SETTLE_US);
             *row_state &= !self.columns.read();
             row_pin.set_high();
         }
 
         state
     }
-}
 
-fn state_to_switches(state: [u8; 9]) -> [Switch; 46] {
-    let mut keys = [Switch::NONE; 46];
-    let mut index = 0;
-    for (n, row) in state.iter().enumerate() {
-        let start = 0x10 * n as u8;
-        for col in [1u8, 2, 4, 8, 16, 32].iter() {
-            if let Some(key) = col_to_key(start, *row, *col) {
-                keys[index] = key;
-                index += 1;
+    /// Drives every row low and arms falling edge triggers on the column
+    /// lines so that any key press wakes the MCU.
+    ///
+    /// Scanning drives the rows high again, so this has to be called again
+    /// after a scan before going back to sleep.
+    pub fn enable_wakeup(&mut self, mode: WakeMode) {
+        for row_pin in self.rows.iter_mut() {
+            row_pin.set_low();
+        }
+
+        unsafe {
+            let syscfg = &*SYSCFG::ptr();
+            let exti = &*EXTI::ptr();
+
+            // Route EXTI lines 0 to 5 to port C.
+            syscfg.exticr1.write(|w| {
+                w.bits(EXTI_PORT_C | EXTI_PORT_C << 4 | EXTI_PORT_C << 8 | EXTI_PORT_C << 12)
+            });
+            syscfg
+                .exticr2
+                .modify(|r, w| w.bits(r.bits() & !0xff | EXTI_PORT_C | EXTI_PORT_C << 4));
+
+            exti.rtsr.modify(|r, w| w.bits(r.bits() & !COLUMN_MASK));
+            exti.ftsr.modify(|r, w| w.bits(r.bits() | COLUMN_MASK));
+            exti.pr.write(|w| w.bits(COLUMN_MASK));
+
+            match mode {
+                WakeMode::Event => {
+                    exti.emr.modify(|r, w| w.bits(r.bits() | COLUMN_MASK));
+                }
+                WakeMode::Interrupt => {
+                    exti.imr.modify(|r, w| w.bits(r.bits() | COLUMN_MASK));
+                    for interrupt in COLUMN_INTERRUPTS {
+                        NVIC::unmask(interrupt);
+                    }
+                }
             }
         }
     }
-    keys
+
+    /// Disarms the column triggers, masks the interrupts unmasked by
+    /// `WakeMode::Interrupt` and drives the rows high again.
+    ///
+    /// EXTI9_5 is shared with lines 6 to 9, which have to be unmasked again
+    /// if they are used elsewhere.
+    pub fn disable_wakeup(&mut self) {
+        unsafe {
+            let exti = &*EXTI::ptr();
+
+            exti.imr.modify(|r, w| w.bits(r.bits() & !COLUMN_MASK));
+            exti.emr.modify(|r, w| w.bits(r.bits() & !COLUMN_MASK));
+            exti.ftsr.modify(|r, w| w.bits(r.bits() & !COLUMN_MASK));
+        }
+
+        for interrupt in COLUMN_INTERRUPTS {
+            NVIC::mask(interrupt);
+        }
+
+        Self::clear_wakeup();
+
+        for row_pin in self.rows.iter_mut() {
+            row_pin.set_high();
+        }
+    }
+
+    /// Clears the pending column interrupts, call this from the EXTI
+    /// handlers when using `WakeMode::Interrupt`.
+    pub fn clear_wakeup() {
+        // SAFETY: Writing 1 only clears the pending bits of the column lines
+        unsafe { (*EXTI::ptr()).pr.write(|w| w.bits(COLUMN_MASK)) };
+    }
+
+    /// Returns true if any column is pulled low, only meaningful while the
+    /// rows are driven low by `enable_wakeup`.
+    pub fn any_pressed(&self) -> bool {
+        self.columns.read() as u32 & COLUMN_MASK != COLUMN_MASK
+    }
 }
 
-fn col_to_key(start: u8, row: u8, col: u8) -> Option<Switch> {
-    let key = match col {
-        1 => 1,
-        2 => 2,
-        4 => 3,
-        8 => 4,
-        16 => 5,
-        32 => 6,
-        _ => return None,
-    };
-    if row & col == col {
-        Switch::from_u8(start + key)
-    } else {
-        None
+/// Finds the switches at the corners of rectangles in a scanned state.
+///
+/// When three corners of a rectangle in the matrix are pressed the fourth
+/// one reads as pressed too, so any of the four could be a phantom press.
+pub fn ghost_switches(state: [u8; 9]) -> KeyState {
+    let mut ghosts = [0u8; 9];
+    // Lines read at positions without a switch can't be part of a ghost.
+    let mut rows = state;
+    for (row, mask) in rows.iter_mut().zip(DEFAULT_ROW_MASKS) {
+        *row &= mask;
     }
+    for i in 0..rows.len() {
+        for j in (i + 1)..rows.len() {
+            let shared = rows[i] & rows[j];
+            if shared.count_ones() >= 2 {
+                ghosts[i] |= shared;
+                ghosts[j] |= shared;
+            }
+        }
+    }
+    KeyState::from_rows(ghosts)
+}
+
+pub fn state_to_switches(state: [u8; 9]) -> [Switch; 46] {
+    KeyState::from_rows(state).to_switches()
 }
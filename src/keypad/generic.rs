@@ -0,0 +1,93 @@
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use super::{ScanSource, DEFAULT_ROW_MASKS, SETTLE_US};
+
+/// A key matrix on any `embedded_hal` pins, for other board revisions and
+/// prototype keypads.
+///
+/// Rows are driven low one at a time and a switch reads as pressed when its
+/// column is pulled low, so the rows should be open drain outputs and the
+/// columns pulled up inputs. Up to 9 rows of 6 columns are supported, row
+/// `r` and column `c` map to bit `c` of byte `r` in the scan and to the
+/// `Switch` at that position of the NumWorks keypad. Positions without a
+/// `Switch` are never reported. Pins of different types can be used by
+/// erasing them first.
+pub struct GenericMatrix<R, C, const ROWS: usize, const COLUMNS: usize> {
+    rows: [R; ROWS],
+    columns: [C; COLUMNS],
+    row_masks: [u8; 9],
+}
+
+impl<R, C, const ROWS: usize, const COLUMNS: usize> GenericMatrix<R, C, ROWS, COLUMNS>
+where
+    R: OutputPin,
+    C: InputPin,
+{
+    /// Scans the positions of `DEFAULT_ROW_MASKS` that fit in `ROWS` and
+    /// `COLUMNS`, use `with_row_masks` to leave out more.
+    pub fn new(mut rows: [R; ROWS], columns: [C; COLUMNS]) -> Self {
+        assert!(ROWS <= 9 && COLUMNS <= 6);
+        for row in rows.iter_mut() {
+            let _ = row.set_high();
+        }
+        let mask = (0xffu16 >> (8 - COLUMNS)) as u8;
+        let mut row_masks = [0; 9];
+        for (row_mask, default) in row_masks[..ROWS].iter_mut().zip(DEFAULT_ROW_MASKS) {
+            *row_mask = default & mask;
+        }
+        Self {
+            rows,
+            columns,
+            row_masks,
+        }
+    }
+
+    /// Sets which columns of each row have a switch, the NumWorks layout is
+    /// `DEFAULT_ROW_MASKS`.
+    pub fn with_row_masks(mut self, row_masks: [u8; 9]) -> Self {
+        self.row_masks = row_masks;
+        self
+    }
+
+    pub fn set_row_masks(&mut self, row_masks: [u8; 9]) {
+        self.row_masks = row_masks;
+    }
+
+    pub fn row_masks(&self) -> [u8; 9] {
+        self.row_masks
+    }
+
+    pub fn release(self) -> ([R; ROWS], [C; COLUMNS]) {
+        (self.rows, self.columns)
+    }
+}
+
+impl<R, C, const ROWS: usize, const COLUMNS: usize> ScanSource
+    for GenericMatrix<R, C, ROWS, COLUMNS>
+where
+    R: OutputPin,
+    C: InputPin,
+{
+    fn scan(&mut self, delay: &mut impl DelayUs<u32>) -> [u8; 9] {
+        let mut state = [0; 9];
+
+        for (row_pin, (row_state, mask)) in self
+            .rows
+            .iter_mut()
+            .zip(state.iter_mut().zip(self.row_masks))
+        {
+            let _ = row_pin.set_low();
+            delay.delay_us(SETTLE_US);
+            for (bit, column) in self.columns.iter().enumerate() {
+                if column.is_low().unwrap_or(false) {
+                    *row_state |= 1 << bit;
+                }
+            }
+            *row_state &= mask;
+            let _ = row_pin.set_high();
+        }
+
+        state
+    }
+}
@@ -0,0 +1,142 @@
+use num_traits::FromPrimitive;
+
+use super::{Key, Modifiers, Switch};
+#[cfg(target_arch = "arm")]
+use crate::external_flash::{self, FLASH_END, FLASH_START};
+
+/// Number of switch slots in each layer, one for every row and column of
+/// the matrix even if there is no switch there.
+const SLOTS: usize = 9 * 6;
+
+const MAGIC: [u8; 4] = *b"KMAP";
+const VERSION: u8 = 1;
+
+/// Size of a keymap serialized with `Keymap::to_bytes`.
+pub const KEYMAP_BYTES: usize = MAGIC.len() + 1 + 4 * SLOTS;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum KeyLayer {
+    Base,
+    Shift,
+    Alpha,
+    AlphaShift,
+}
+
+impl KeyLayer {
+    pub const ALL: [KeyLayer; 4] = [
+        KeyLayer::Base,
+        KeyLayer::Shift,
+        KeyLayer::Alpha,
+        KeyLayer::AlphaShift,
+    ];
+
+    /// The key printed on the calculator for this layer.
+    pub fn default_key(&self, switch: Switch) -> Key {
+        match self {
+            Self::Base => switch.to_key(),
+            Self::Shift => switch.to_key_shift(),
+            Self::Alpha => switch.to_key_alpha(false),
+            Self::AlphaShift => switch.to_key_alpha(true),
+        }
+    }
+}
+
+impl From<Modifiers> for KeyLayer {
+    fn from(modifiers: Modifiers) -> Self {
+        match (modifiers.alpha || modifiers.alpha_lock, modifiers.shift) {
+            (true, true) => Self::AlphaShift,
+            (true, false) => Self::Alpha,
+            (false, true) => Self::Shift,
+            (false, false) => Self::Base,
+        }
+    }
+}
+
+/// Maps every switch to a key in each of the four modifier layers.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Keymap {
+    layers: [[Key; SLOTS]; 4],
+}
+
+impl Keymap {
+    pub fn key(&self, switch: Switch, layer: KeyLayer) -> Key {
+        match switch.index() {
+            Some(index) => self.layers[layer as usize][index],
+            None => Key::NONE,
+        }
+    }
+
+    pub fn set_key(&mut self, switch: Switch, layer: KeyLayer, key: Key) {
+        if let Some(index) = switch.index() {
+            self.layers[layer as usize][index] = key;
+        }
+    }
+
+    /// Serializes the keymap as a small header followed by one byte per key,
+    /// using the explicit `Key` values.
+    pub fn to_bytes(&self) -> [u8; KEYMAP_BYTES] {
+        let mut bytes = [0u8; KEYMAP_BYTES];
+        bytes[..MAGIC.len()].copy_from_slice(&MAGIC);
+        bytes[MAGIC.len()] = VERSION;
+        let keys = self.layers.iter().flat_map(|layer| layer.iter());
+        for (byte, key) in bytes[MAGIC.len() + 1..].iter_mut().zip(keys) {
+            *byte = *key as u8;
+        }
+        bytes
+    }
+
+    /// Returns `None` if the bytes were not written by `to_bytes`, for
+    /// example if they are erased flash.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < KEYMAP_BYTES
+            || bytes[..MAGIC.len()] != MAGIC
+            || bytes[MAGIC.len()] != VERSION
+        {
+            return None;
+        }
+        let mut keymap = Self::default();
+        let keys = keymap.layers.iter_mut().flat_map(|layer| layer.iter_mut());
+        for (key, byte) in keys.zip(&bytes[MAGIC.len() + 1..KEYMAP_BYTES]) {
+            *key = Key::from_u8(*byte)?;
+        }
+        Some(keymap)
+    }
+
+    /// Reads a keymap saved in external flash at `address`, `None` if the
+    /// keymap would not be inside the external flash.
+    ///
+    /// # Safety
+    ///
+    /// The external flash must be in memory mapped mode.
+    #[cfg(target_arch = "arm")]
+    pub unsafe fn read_from_flash(address: u32) -> Option<Self> {
+        if address < FLASH_START || address > FLASH_END - KEYMAP_BYTES as u32 {
+            return None;
+        }
+        let bytes = core::slice::from_raw_parts(address as *const u8, KEYMAP_BYTES);
+        Self::from_bytes(bytes)
+    }
+
+    /// Writes the keymap to external flash at `address`, the area must have
+    /// been erased first.
+    #[cfg(target_arch = "arm")]
+    pub fn write_to_flash(&self, address: u32) {
+        external_flash::write_memory(address, &self.to_bytes());
+    }
+}
+
+/// The mapping printed on the calculator.
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut layers = [[Key::NONE; SLOTS]; 4];
+        for layer in KeyLayer::ALL {
+            for (index, key) in layers[layer as usize].iter_mut().enumerate() {
+                if let Some(switch) = Switch::from_index(index) {
+                    *key = layer.default_key(switch);
+                }
+            }
+        }
+        Self { layers }
+    }
+}
@@ -0,0 +1,206 @@
+use heapless::Vec;
+
+use super::{Instant, KeyState};
+#[cfg(target_arch = "arm")]
+use crate::external_flash::{self, FLASH_END, FLASH_START};
+
+const MAGIC: [u8; 4] = *b"KREC";
+const HEADER_BYTES: usize = MAGIC.len() + 4;
+const FRAME_BYTES: usize = 4 + 8;
+
+/// The key pad state from `time` milliseconds after the recording started.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    pub time: u32,
+    pub state: KeyState,
+}
+
+/// Records up to `N` changes of the key pad state.
+///
+/// Recording the switch state rather than the resulting events means that
+/// playback goes through the same modifier, keymap, repeat and long-press
+/// handling as real key presses.
+pub struct Recording<const N: usize> {
+    frames: Vec<Frame, N>,
+    start: Option<Instant>,
+}
+
+impl<const N: usize> Recording<N> {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            start: None,
+        }
+    }
+
+    /// Adds `state` if it differs from the last recorded state, returns false
+    /// if the recording is full. The first call sets the start time.
+    pub fn record(&mut self, now: Instant, state: KeyState) -> bool {
+        let start = *self.start.get_or_insert(now);
+        if self.frames.last().map(|f| f.state) == Some(state) {
+            return true;
+        }
+        let time = (now - start).to_millis();
+        self.frames.push(Frame { time, state }).is_ok()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.start = None;
+    }
+
+    /// Number of bytes needed by `write_bytes` for this recording.
+    pub fn byte_len(&self) -> usize {
+        HEADER_BYTES + self.frames.len() * FRAME_BYTES
+    }
+
+    /// Serializes the recording into `buffer`, returning the number of bytes
+    /// used or `None` if the buffer is too small.
+    pub fn write_bytes(&self, buffer: &mut [u8]) -> Option<usize> {
+        let length = self.byte_len();
+        if buffer.len() < length {
+            return None;
+        }
+        buffer[..MAGIC.len()].copy_from_slice(&MAGIC);
+        buffer[MAGIC.len()..HEADER_BYTES]
+            .copy_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for (frame, bytes) in self
+            .frames
+            .iter()
+            .zip(buffer[HEADER_BYTES..length].chunks_exact_mut(FRAME_BYTES))
+        {
+            bytes[..4].copy_from_slice(&frame.time.to_le_bytes());
+            bytes[4..].copy_from_slice(&frame.state.0.to_le_bytes());
+        }
+        Some(length)
+    }
+
+    /// Reads a recording written by `write_bytes`, frames past `N` are
+    /// dropped. Returns `None` if `bytes` is shorter than the frames it
+    /// should hold.
+    pub fn read_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_BYTES || bytes[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&bytes[MAGIC.len()..HEADER_BYTES]);
+        let count = (u32::from_le_bytes(count) as usize).min(N);
+        if bytes.len() < HEADER_BYTES + count * FRAME_BYTES {
+            return None;
+        }
+        let frames = bytes[HEADER_BYTES..].chunks_exact(FRAME_BYTES).take(count);
+
+        let mut recording = Self::new();
+        for bytes in frames {
+            let mut time = [0u8; 4];
+            let mut state = [0u8; 8];
+            time.copy_from_slice(&bytes[..4]);
+            state.copy_from_slice(&bytes[4..]);
+            let frame = Frame {
+                time: u32::from_le_bytes(time),
+                state: KeyState(u64::from_le_bytes(state)),
+            };
+            recording.frames.push(frame).unwrap();
+        }
+        Some(recording)
+    }
+
+    /// Writes the recording to external flash at `address`, the area must
+    /// have been erased first. `buffer` is used to serialize the recording.
+    #[cfg(target_arch = "arm")]
+    pub fn write_to_flash(&self, address: u32, buffer: &mut [u8]) -> bool {
+        match self.write_bytes(buffer) {
+            Some(length) => {
+                external_flash::write_memory(address, &buffer[..length]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads a recording saved in external flash at `address`, `None` if the
+    /// recording would not be inside the external flash.
+    ///
+    /// # Safety
+    ///
+    /// The external flash must be in memory mapped mode.
+    #[cfg(target_arch = "arm")]
+    pub unsafe fn read_from_flash(address: u32) -> Option<Self> {
+        if address < FLASH_START || address > FLASH_END - HEADER_BYTES as u32 {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(address as *const u8, HEADER_BYTES);
+        if header[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&header[MAGIC.len()..]);
+        let length = HEADER_BYTES + u32::from_le_bytes(count).min(N as u32) as usize * FRAME_BYTES;
+        if length > (FLASH_END - address) as usize {
+            return None;
+        }
+        Self::read_bytes(core::slice::from_raw_parts(address as *const u8, length))
+    }
+}
+
+impl<const N: usize> Default for Recording<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Replays recorded frames with their original timing.
+///
+/// ```ignore
+/// if let Some(state) = player.poll(now) {
+///     keypad.inject(Some(state));
+/// } else if player.is_finished() {
+///     keypad.inject(None);
+/// }
+/// let events = keypad.poll_events(now, &mut delay);
+/// ```
+pub struct Player<'a> {
+    frames: &'a [Frame],
+    index: usize,
+    start: Option<Instant>,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(frames: &'a [Frame]) -> Self {
+        Self {
+            frames,
+            index: 0,
+            start: None,
+        }
+    }
+
+    /// Returns the most recent frame's state if a new frame became due since
+    /// the last call. The first call sets the start time.
+    pub fn poll(&mut self, now: Instant) -> Option<KeyState> {
+        let start = *self.start.get_or_insert(now);
+        let elapsed = (now - start).to_millis();
+        let mut state = None;
+        while let Some(frame) = self.frames.get(self.index) {
+            if frame.time > elapsed {
+                break;
+            }
+            state = Some(frame.state);
+            self.index += 1;
+        }
+        state
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.frames.len()
+    }
+
+    pub fn restart(&mut self) {
+        self.index = 0;
+        self.start = None;
+    }
+}
@@ -0,0 +1,135 @@
+use cortex_m::peripheral::NVIC;
+use embedded_hal::blocking::delay::DelayUs;
+use heapless::spsc::Producer;
+use stm32f7xx_hal::pac::{Interrupt, TIM3};
+use stm32f7xx_hal::rcc::Clocks;
+
+use super::{Duration, Instant, KeyEvent, KeyMatrix, KeyPad};
+
+/// TIM3 counts at 10 kHz, so its 16 bit reload register fits periods of up
+/// to 6.5 s.
+const TIMER_HZ: u32 = 10_000;
+const MAX_PERIOD_MS: u32 = 0x1_0000 / (TIMER_HZ / 1000);
+
+const TIM_CR1_CEN: u32 = 1;
+const TIM_DIER_UIE: u32 = 1;
+const TIM_EGR_UG: u32 = 1;
+const TIM_SR_UIF: u32 = 1;
+
+/// Busy waits using the core clock, usable from an interrupt handler where
+/// the SysTick delay is not available.
+pub struct CycleDelay {
+    cycles_per_us: u32,
+}
+
+impl CycleDelay {
+    pub fn new(clocks: &Clocks) -> Self {
+        Self {
+            cycles_per_us: clocks.hclk().to_MHz(),
+        }
+    }
+}
+
+impl DelayUs<u32> for CycleDelay {
+    fn delay_us(&mut self, us: u32) {
+        cortex_m::asm::delay(us * self.cycles_per_us);
+    }
+}
+
+/// Scans the key pad from the TIM3 interrupt and pushes the resulting events
+/// into a queue, so the main loop only has to dequeue them.
+///
+/// The scanner has to be reachable from the interrupt handler, for example
+/// through a `cortex_m::interrupt::Mutex`, and `on_interrupt` must be called
+/// from the `TIM3` handler.
+pub struct BackgroundScanner<'a, const N: usize> {
+    keypad: KeyPad<KeyMatrix>,
+    producer: Producer<'a, KeyEvent, N>,
+    delay: CycleDelay,
+    period: Duration,
+    now: Instant,
+    dropped: u32,
+}
+
+impl<'a, const N: usize> BackgroundScanner<'a, N> {
+    /// Starts TIM3 so that it fires every `period`, TIM3's clock is enabled
+    /// by `init_clocks`. Panics if `period` is zero or longer than 6.5 s.
+    pub fn new(
+        keypad: KeyPad<KeyMatrix>,
+        producer: Producer<'a, KeyEvent, N>,
+        clocks: &Clocks,
+        period: Duration,
+    ) -> Self {
+        let prescaler = clocks.timclk1().to_Hz() / TIMER_HZ - 1;
+        assert!((1..=MAX_PERIOD_MS).contains(&period.to_millis()));
+        let reload = period.to_millis() * (TIMER_HZ / 1000) - 1;
+
+        unsafe {
+            let tim = &*TIM3::ptr();
+
+            tim.cr1.write(|w| w.bits(0));
+            tim.psc.write(|w| w.bits(prescaler));
+            tim.arr.write(|w| w.bits(reload));
+            // Load the prescaler now instead of at the first update.
+            tim.egr.write(|w| w.bits(TIM_EGR_UG));
+            tim.sr.write(|w| w.bits(0));
+            tim.dier.write(|w| w.bits(TIM_DIER_UIE));
+            tim.cr1.write(|w| w.bits(TIM_CR1_CEN));
+
+            NVIC::unmask(Interrupt::TIM3);
+        }
+
+        Self {
+            keypad,
+            producer,
+            delay: CycleDelay::new(clocks),
+            period,
+            now: Instant::from_ticks(0),
+            dropped: 0,
+        }
+    }
+
+    /// Scans the matrix once, call this from the `TIM3` interrupt handler.
+    pub fn on_interrupt(&mut self) {
+        // SAFETY: SR is rc_w0, writing 1 to the other flags leaves them as
+        // they are.
+        unsafe { (*TIM3::ptr()).sr.write(|w| w.bits(!TIM_SR_UIF)) };
+
+        self.now += self.period;
+
+        for event in self.keypad.poll_events(self.now, &mut self.delay) {
+            if self.producer.enqueue(event).is_err() {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Time since the scanner was started, counted in scan periods.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// Number of events lost because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut KeyPad<KeyMatrix> {
+        &mut self.keypad
+    }
+
+    /// Stops TIM3 and gives the key pad back.
+    pub fn stop(self) -> KeyPad<KeyMatrix> {
+        NVIC::mask(Interrupt::TIM3);
+
+        unsafe {
+            let tim = &*TIM3::ptr();
+
+            tim.cr1.write(|w| w.bits(0));
+            tim.dier.write(|w| w.bits(0));
+            tim.sr.write(|w| w.bits(0));
+        }
+
+        self.keypad
+    }
+}
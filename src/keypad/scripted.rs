@@ -0,0 +1,55 @@
+use embedded_hal::blocking::delay::DelayUs;
+
+use super::{KeyState, ScanSource};
+
+/// A `ScanSource` that returns one state from a script per scan, then reports
+/// every key released once the script runs out.
+///
+/// ```ignore
+/// let script = [
+///     [Switch::R2C1].into_iter().collect(), // Shift
+///     KeyState::empty(),
+///     [Switch::R5C1].into_iter().collect(), // 7
+///     KeyState::empty(),
+/// ];
+/// let mut keypad = KeyPad::new(ScriptedMatrix::new(&script));
+/// let keys = keypad.read(&mut NoDelay);
+/// ```
+pub struct ScriptedMatrix<'a> {
+    script: &'a [KeyState],
+    index: usize,
+}
+
+impl<'a> ScriptedMatrix<'a> {
+    pub fn new(script: &'a [KeyState]) -> Self {
+        Self { script, index: 0 }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.script.len()
+    }
+
+    pub fn restart(&mut self) {
+        self.index = 0;
+    }
+}
+
+impl ScanSource for ScriptedMatrix<'_> {
+    fn scan(&mut self, _delay: &mut impl DelayUs<u32>) -> [u8; 9] {
+        let state = self
+            .script
+            .get(self.index)
+            .copied()
+            .unwrap_or_else(KeyState::empty);
+        self.index = (self.index + 1).min(self.script.len());
+        state.to_rows()
+    }
+}
+
+/// A delay that returns immediately, for scan sources that do not need to
+/// wait for the lines to settle.
+pub struct NoDelay;
+
+impl DelayUs<u32> for NoDelay {
+    fn delay_us(&mut self, _us: u32) {}
+}
@@ -0,0 +1,112 @@
+use heapless::Vec;
+
+use super::{KeyState, Switch};
+
+/// What happens when a shortcut's chord is completed.
+#[derive(Debug, Clone, Copy)]
+pub enum ShortcutAction {
+    /// Called from `KeyPad::read` or `KeyPad::poll_events` during the scan
+    /// that completed the chord.
+    Callback(fn()),
+    /// Reported by `KeyPad::poll_events` as `KeyEvent::Shortcut` with this
+    /// id.
+    Event(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Shortcut {
+    pub chord: KeyState,
+    pub action: ShortcutAction,
+}
+
+/// Up to `N` global shortcuts, each triggered by holding a chord of switches.
+///
+/// A shortcut fires when the last switch of its chord is pressed. From then
+/// on, the chord's switches are hidden from normal key delivery until they
+/// are released, so they produce no presses, repeats, long presses or
+/// releases, even for the switches held before the chord completed. If
+/// several chords complete at once the one with the most switches wins.
+///
+/// ```ignore
+/// // Home and Back
+/// let chord = [Switch::R1C1, Switch::R0C6].into_iter().collect();
+/// keypad
+///     .shortcuts_mut()
+///     .bind(chord, ShortcutAction::Callback(reset))
+///     .unwrap();
+/// ```
+pub struct Shortcuts<const N: usize> {
+    shortcuts: Vec<Shortcut, N>,
+    last: KeyState,
+    suppressed: KeyState,
+}
+
+impl<const N: usize> Shortcuts<N> {
+    pub fn new() -> Self {
+        Self {
+            shortcuts: Vec::new(),
+            last: KeyState::empty(),
+            suppressed: KeyState::empty(),
+        }
+    }
+
+    /// Binds `chord` to `action`, replacing any action already bound to the
+    /// same chord. Gives the shortcut back if the chord is empty or all `N`
+    /// slots are in use.
+    pub fn bind(&mut self, chord: KeyState, action: ShortcutAction) -> Result<(), Shortcut> {
+        let shortcut = Shortcut { chord, action };
+        if chord.is_empty() {
+            return Err(shortcut);
+        }
+        match self.shortcuts.iter_mut().find(|s| s.chord == chord) {
+            Some(existing) => {
+                *existing = shortcut;
+                Ok(())
+            }
+            None => self.shortcuts.push(shortcut),
+        }
+    }
+
+    pub fn unbind(&mut self, chord: KeyState) -> Option<Shortcut> {
+        let index = self.shortcuts.iter().position(|s| s.chord == chord)?;
+        Some(self.shortcuts.swap_remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.shortcuts.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shortcut> {
+        self.shortcuts.iter()
+    }
+
+    /// Returns `state` without the switches held for a shortcut, and the
+    /// shortcut completed by this scan along with the switch that completed
+    /// it.
+    pub(crate) fn update(&mut self, state: KeyState) -> (KeyState, Option<(Switch, Shortcut)>) {
+        let pressed = state.pressed_since(self.last);
+        self.last = state;
+        self.suppressed = KeyState(self.suppressed.0 & state.0);
+
+        let fired = self
+            .shortcuts
+            .iter()
+            .filter(|s| state.contains(s.chord) && pressed.0 & s.chord.0 != 0)
+            .max_by_key(|s| s.chord.count())
+            .copied();
+
+        let fired = fired.and_then(|shortcut| {
+            self.suppressed = KeyState(self.suppressed.0 | shortcut.chord.0);
+            let switch = KeyState(pressed.0 & shortcut.chord.0).iter().next()?;
+            Some((switch, shortcut))
+        });
+
+        (KeyState(state.0 & !self.suppressed.0), fired)
+    }
+}
+
+impl<const N: usize> Default for Shortcuts<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,284 @@
+use super::keymap::KEYMAP_BYTES;
+use super::recording::{Player, Recording};
+use super::*;
+
+fn state(switches: &[Switch]) -> KeyState {
+    switches.iter().copied().collect()
+}
+
+fn keypad(script: &[KeyState]) -> KeyPad<ScriptedMatrix<'_>> {
+    KeyPad::new(ScriptedMatrix::new(script))
+}
+
+fn at(ms: u32) -> Instant {
+    Instant::from_ticks(ms)
+}
+
+#[test]
+fn state_to_switches_lists_pressed_switches_in_order() {
+    let rows = state(&[Switch::R4C1, Switch::R0C2]).to_rows();
+    let switches = state_to_switches(rows);
+    assert_eq!(switches[..3], [Switch::R0C2, Switch::R4C1, Switch::NONE]);
+}
+
+#[test]
+fn from_rows_drops_positions_without_a_switch() {
+    // R1C2 and R5C6 have no switch.
+    let state = KeyState::from_rows([0, 0b10, 0, 0, 0, 0b100000, 0, 0, 0]);
+    assert!(state.is_empty());
+    assert_eq!(state.count(), 0);
+}
+
+#[test]
+fn read_resolves_switches_through_the_keymap() {
+    let script = [state(&[Switch::R5C1])];
+    let keys = keypad(&script).read(&mut NoDelay);
+    assert_eq!(keys[..2], [Key::Seven, Key::NONE]);
+}
+
+#[test]
+fn held_shift_applies_to_keys_pressed_in_the_same_scan() {
+    let script = [state(&[Switch::R2C1, Switch::R4C1])];
+    let keys = keypad(&script).read(&mut NoDelay);
+    assert_eq!(keys[..2], [Key::Shift, Key::ASin]);
+}
+
+#[test]
+fn one_shot_shift_applies_to_the_next_key_only() {
+    let sin = state(&[Switch::R4C1]);
+    let script = [
+        state(&[Switch::R2C1]),
+        KeyState::empty(),
+        sin,
+        sin,
+        KeyState::empty(),
+        sin,
+    ];
+    let mut keypad = keypad(&script);
+    keypad.read(&mut NoDelay);
+    keypad.read(&mut NoDelay);
+    assert!(keypad.modifiers().shift);
+
+    assert_eq!(keypad.read(&mut NoDelay)[0], Key::ASin);
+    assert!(!keypad.modifiers().shift);
+    // A held key keeps the key it was pressed as.
+    assert_eq!(keypad.read(&mut NoDelay)[0], Key::ASin);
+
+    keypad.read(&mut NoDelay);
+    assert_eq!(keypad.read(&mut NoDelay)[0], Key::Sin);
+}
+
+#[test]
+fn shift_pressed_with_another_key_is_used_up_by_it() {
+    let script = [
+        state(&[Switch::R2C1, Switch::R0C6]),
+        KeyState::empty(),
+        state(&[Switch::R4C1]),
+    ];
+    let mut keypad = keypad(&script);
+    assert_eq!(keypad.read(&mut NoDelay)[..2], [Key::Back, Key::Shift]);
+    keypad.read(&mut NoDelay);
+    assert_eq!(keypad.read(&mut NoDelay)[0], Key::Sin);
+}
+
+#[test]
+fn double_alpha_locks_alpha() {
+    let alpha = state(&[Switch::R2C2]);
+    let script = [
+        alpha,
+        KeyState::empty(),
+        alpha,
+        KeyState::empty(),
+        state(&[Switch::R3C1]),
+    ];
+    let mut keypad = keypad(&script);
+    for _ in 0..4 {
+        keypad.read(&mut NoDelay);
+    }
+    assert!(keypad.modifiers().alpha_lock);
+
+    assert_eq!(keypad.read(&mut NoDelay)[0], Key::A);
+    assert!(keypad.modifiers().alpha_lock);
+}
+
+#[test]
+fn held_keys_report_the_key_resolved_at_press_time() {
+    let delete = state(&[Switch::R2C6]);
+    let script = [
+        state(&[Switch::R2C1]),
+        KeyState::empty(),
+        delete,
+        delete,
+        KeyState::empty(),
+    ];
+    let mut keypad = keypad(&script);
+    keypad.poll_events(at(0), &mut NoDelay);
+    keypad.poll_events(at(10), &mut NoDelay);
+
+    let events = keypad.poll_events(at(20), &mut NoDelay);
+    assert!(matches!(events[..], [KeyEvent::Pressed { .. }]));
+    assert_eq!(events[0].key(), Key::Clear);
+    let events = keypad.poll_events(at(520), &mut NoDelay);
+    assert!(matches!(events[..], [KeyEvent::Repeated { .. }]));
+    assert_eq!(events[0].key(), Key::Clear);
+    let events = keypad.poll_events(at(530), &mut NoDelay);
+    assert!(matches!(events[..], [KeyEvent::Released { .. }]));
+    assert_eq!(events[0].key(), Key::Clear);
+}
+
+#[test]
+fn short_press_follows_release() {
+    let script = [state(&[Switch::R1C1]), KeyState::empty()];
+    let mut keypad = keypad(&script);
+    keypad.poll_events(at(0), &mut NoDelay);
+    let events = keypad.poll_events(at(100), &mut NoDelay);
+    assert!(matches!(
+        events[..],
+        [
+            KeyEvent::Released { key: Key::Home, .. },
+            KeyEvent::ShortPressed { key: Key::Home, .. },
+        ]
+    ));
+}
+
+#[test]
+fn shortcuts_take_held_switches_without_releasing_them() {
+    let back = state(&[Switch::R0C6]);
+    let chord = state(&[Switch::R0C6, Switch::R1C1]);
+    let script = [back, chord, KeyState::empty()];
+    let mut keypad = keypad(&script);
+    keypad
+        .shortcuts_mut()
+        .bind(chord, ShortcutAction::Event(1))
+        .unwrap();
+    keypad.poll_events(at(0), &mut NoDelay);
+
+    let events = keypad.poll_events(at(10), &mut NoDelay);
+    assert!(matches!(events[..], [KeyEvent::Shortcut { id: 1, .. }]));
+    assert_eq!(keypad.last_state(), chord);
+    // Past the long press threshold, nothing is left to fire.
+    assert!(keypad.poll_events(at(2000), &mut NoDelay).is_empty());
+}
+
+#[test]
+fn remapped_keys_are_read_from_the_keymap() {
+    let script = [state(&[Switch::R5C1])];
+    let mut keypad = keypad(&script);
+    keypad
+        .keymap_mut()
+        .set_key(Switch::R5C1, KeyLayer::Base, Key::Exclamation);
+    assert_eq!(keypad.read(&mut NoDelay)[0], Key::Exclamation);
+}
+
+#[test]
+fn keymap_round_trips_through_bytes() {
+    let mut keymap = Keymap::default();
+    keymap.set_key(Switch::R0C1, KeyLayer::AlphaShift, Key::Question);
+    assert_eq!(Keymap::from_bytes(&keymap.to_bytes()), Some(keymap));
+    assert_eq!(Keymap::from_bytes(&[0xff; KEYMAP_BYTES]), None);
+}
+
+#[test]
+fn recordings_shorter_than_their_header_are_rejected() {
+    let mut recording: Recording<4> = Recording::new();
+    recording.record(at(0), state(&[Switch::R0C1]));
+    recording.record(at(50), KeyState::empty());
+    let mut buffer = [0; 64];
+    let length = recording.write_bytes(&mut buffer).unwrap();
+    assert!(Recording::<4>::read_bytes(&buffer[..length - 1]).is_none());
+}
+
+#[test]
+fn debouncer_accepts_a_change_after_enough_samples() {
+    let mut debouncer = Debouncer::new(3);
+    let pressed = [1, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(debouncer.update(pressed), [0; 9]);
+    assert_eq!(debouncer.update(pressed), [0; 9]);
+    assert_eq!(debouncer.update(pressed), pressed);
+}
+
+#[test]
+fn debouncer_restarts_the_count_on_a_bounce() {
+    let mut debouncer = Debouncer::new(3);
+    let pressed = [1, 0, 0, 0, 0, 0, 0, 0, 0];
+    debouncer.update(pressed);
+    debouncer.update(pressed);
+    debouncer.update([0; 9]);
+    assert_eq!(debouncer.update(pressed), [0; 9]);
+    assert_eq!(debouncer.update(pressed), [0; 9]);
+    assert_eq!(debouncer.update(pressed), pressed);
+}
+
+#[test]
+fn ghost_scans_are_dropped() {
+    let corners = state(&[Switch::R2C1, Switch::R2C2, Switch::R3C1, Switch::R3C2]);
+    assert_eq!(ghost_switches(corners.to_rows()), corners);
+
+    let script = [state(&[Switch::R0C1]), corners];
+    let mut keypad = keypad(&script);
+    keypad.set_ghost_policy(GhostPolicy::Drop);
+    keypad.read(&mut NoDelay);
+    assert_eq!(keypad.read(&mut NoDelay)[..2], [Key::Left, Key::NONE]);
+    assert_eq!(keypad.ghosts(), corners);
+}
+
+#[test]
+fn recordings_round_trip_through_bytes() {
+    let mut recording: Recording<4> = Recording::new();
+    recording.record(at(100), state(&[Switch::R0C1]));
+    recording.record(at(130), KeyState::empty());
+    recording.record(at(180), state(&[Switch::R0C2]));
+    let mut buffer = [0; 64];
+    let length = recording.write_bytes(&mut buffer).unwrap();
+    let read = Recording::<4>::read_bytes(&buffer[..length]).unwrap();
+    assert_eq!(read.frames(), recording.frames());
+}
+
+#[test]
+fn player_replays_frames_at_their_recorded_times() {
+    let mut recording: Recording<4> = Recording::new();
+    recording.record(at(100), state(&[Switch::R0C1]));
+    recording.record(at(130), KeyState::empty());
+    recording.record(at(180), state(&[Switch::R0C2]));
+
+    let mut player = Player::new(recording.frames());
+    assert_eq!(player.poll(at(1000)), Some(state(&[Switch::R0C1])));
+    assert_eq!(player.poll(at(1020)), None);
+    assert_eq!(player.poll(at(1030)), Some(KeyState::empty()));
+    assert!(!player.is_finished());
+    assert_eq!(player.poll(at(1200)), Some(state(&[Switch::R0C2])));
+    assert!(player.is_finished());
+}
+
+#[test]
+fn names_parse_back_to_the_same_key_and_switch() {
+    for value in 0..=u8::MAX {
+        if let Some(key) = Key::from_u8(value) {
+            assert_eq!(key.name().parse(), Ok(key));
+        }
+        if let Some(switch) = Switch::from_u8(value) {
+            assert_eq!(switch.name().parse(), Ok(switch));
+        }
+    }
+}
+
+#[test]
+fn letters_are_uppercase_with_shift_and_alpha() {
+    let shift = Modifiers {
+        shift: true,
+        ..Modifiers::default()
+    };
+    let upper = Modifiers {
+        alpha: true,
+        ..shift
+    };
+    let locked = Modifiers {
+        alpha_lock: true,
+        ..shift
+    };
+    assert_eq!(Key::Q.token_for(Modifiers::default(), false), "q");
+    assert_eq!(Key::Q.token_for(shift, false), "q");
+    assert_eq!(Key::Q.token_for(upper, false), "Q");
+    assert_eq!(Key::Z.token_for(locked, true), "Z");
+    assert_eq!(Key::Seven.token_for(upper, false), Key::Seven.token());
+}
@@ -1,7 +1,12 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+//! Only the key pad logic builds for other targets, so it can be tested on
+//! the host with `cargo test --target x86_64-unknown-linux-gnu`.
 
+#[cfg(target_arch = "arm")]
 use cortex_m::peripheral::MPU;
-use display::Display;
+#[cfg(target_arch = "arm")]
+use display::{Display, LcdTiming, TimingError};
+#[cfg(target_arch = "arm")]
 use hal::{
     flash::Flash,
     fmc_lcd::{ChipSelect1, LcdPins},
@@ -14,31 +19,67 @@ use hal::{
     rcc::Clocks,
     timer::SysTimerExt,
 };
+#[cfg(target_arch = "arm")]
 use keypad::{KeyMatrix, KeyPad};
+#[cfg(target_arch = "arm")]
 use led::Led;
 
+#[cfg(target_arch = "arm")]
 pub use clocks::init_clocks;
+#[cfg(target_arch = "arm")]
 pub use stm32f7xx_hal as hal;
 
+#[cfg(target_arch = "arm")]
+pub mod benchmark;
+#[cfg(target_arch = "arm")]
+pub mod clipboard;
+#[cfg(target_arch = "arm")]
 pub mod clocks;
+#[cfg(target_arch = "arm")]
+pub mod compositor;
+#[cfg(target_arch = "arm")]
+pub mod diagnostic;
+#[cfg(target_arch = "arm")]
 pub mod display;
+#[cfg(target_arch = "arm")]
 pub mod external_flash;
+#[cfg(target_arch = "arm")]
+pub mod gray_font;
 pub mod keypad;
+#[cfg(target_arch = "arm")]
 pub mod led;
+#[cfg(target_arch = "arm")]
+pub mod regions;
+#[cfg(target_arch = "arm")]
+pub mod viewer;
 
 pub const HCLK: u32 = 192_000_000;
 
+#[cfg(target_arch = "arm")]
 use hal::otg_fs::USB;
+#[cfg(target_arch = "arm")]
 use usb_device::class_prelude::UsbBusAllocator;
 
+#[cfg(target_arch = "arm")]
 pub fn get_internal_flash() -> Flash {
     let dp = unsafe { pac::Peripherals::steal() };
 
     Flash::new(dp.FLASH)
 }
 
+/// Init MPU before doing this. Fails if the default timing does not fit the
+/// FMC at the current HCLK, which happens from 200 MHz up.
+#[cfg(target_arch = "arm")]
+pub fn get_display(clocks: &Clocks) -> Result<Display, TimingError> {
+    get_display_with_timing(clocks, &LcdTiming::default())
+}
+
 /// Init MPU before doing this.
-pub fn get_display(clocks: &Clocks) -> Display {
+#[cfg(target_arch = "arm")]
+pub fn get_display_with_timing(
+    clocks: &Clocks,
+    timing: &LcdTiming,
+) -> Result<Display, TimingError> {
     let dp = unsafe { pac::Peripherals::steal() };
     let cp = unsafe { cortex_m::Peripherals::steal() };
 
@@ -84,10 +125,12 @@ pub fn get_display(clocks: &Clocks) -> Display {
         gpiod.pd6.into_push_pull_output(),
         &mut delay,
         clocks,
+        timing,
     )
 }
 
-pub fn get_keypad() -> KeyPad {
+#[cfg(target_arch = "arm")]
+pub fn get_keypad() -> KeyPad<KeyMatrix> {
     let dp = unsafe { pac::Peripherals::steal() };
 
     let gpioa = dp.GPIOA.split();
@@ -101,8 +144,10 @@ pub fn get_keypad() -> KeyPad {
     KeyPad::new(keymatrix)
 }
 
+#[cfg(target_arch = "arm")]
 pub type TopLed = Led<PB4<Output<PushPull>>, PB5<Output<PushPull>>, PB0<Output<PushPull>>>;
 
+#[cfg(target_arch = "arm")]
 pub fn get_led() -> TopLed {
     let dp = unsafe { pac::Peripherals::steal() };
 
@@ -115,6 +160,7 @@ pub fn get_led() -> TopLed {
     )
 }
 
+#[cfg(target_arch = "arm")]
 pub fn get_usb_bus_allocator(
     clocks: &Clocks,
     ep_memory: &'static mut [u32],
@@ -134,6 +180,7 @@ pub fn get_usb_bus_allocator(
     UsbBus::new(usb, ep_memory)
 }
 
+#[cfg(target_arch = "arm")]
 pub fn init_mpu() {
     cortex_m::asm::dmb();
 
@@ -0,0 +1,216 @@
+use core::fmt::Write;
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoFont, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+};
+use embedded_text::{plugin::tail::Tail, TextBox};
+use heapless::{String, Vec};
+
+use crate::display::{Display, BG_COLOUR, BOTTOM_REGION, TEXT_COLOUR, TOP_REGION};
+
+/// How a region behaves once its text no longer fits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scroll {
+    /// Show the last lines and drop the oldest text when the buffer is full,
+    /// like the console.
+    Tail,
+    /// Show the text from the start and refuse writes that do not fit, like
+    /// the input line.
+    Fixed,
+}
+
+pub struct Region<const N: usize> {
+    pub name: &'static str,
+    pub bounds: Rectangle,
+    pub font: &'static MonoFont<'static>,
+    pub colour: Rgb565,
+    pub scroll: Scroll,
+    text: String<N>,
+    dirty: bool,
+}
+
+impl<const N: usize> Region<N> {
+    pub fn new(name: &'static str, bounds: Rectangle, scroll: Scroll) -> Self {
+        Self {
+            name,
+            bounds,
+            font: &FONT_6X10,
+            colour: TEXT_COLOUR,
+            scroll,
+            text: String::new(),
+            dirty: true,
+        }
+    }
+
+    pub fn with_font(mut self, font: &'static MonoFont<'static>) -> Self {
+        self.font = font;
+        self
+    }
+
+    pub fn with_colour(mut self, colour: Rgb565) -> Self {
+        self.colour = colour;
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Appends `text`, returns false if the region is `Scroll::Fixed` and the
+    /// text did not fit.
+    pub fn write(&mut self, text: &str) -> bool {
+        if self.text.len() + text.len() > N {
+            if self.scroll == Scroll::Fixed {
+                return false;
+            }
+            if text.len() >= N {
+                self.text.clear();
+                self.text.push_str(tail(text, N)).unwrap();
+                self.dirty = true;
+                return true;
+            }
+            let old = self.text.clone();
+            self.text.clear();
+            self.text.push_str(tail(&old, N - text.len())).unwrap();
+        }
+        self.text.push_str(text).unwrap();
+        self.dirty = true;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<char> {
+        self.dirty = true;
+        self.text.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn draw(&mut self, display: &mut Display, clear: bool) {
+        let character_style = MonoTextStyle::new(self.font, self.colour);
+
+        if clear {
+            self.bounds
+                .into_styled(PrimitiveStyleBuilder::new().fill_color(BG_COLOUR).build())
+                .draw(&mut display.display)
+                .unwrap();
+        }
+
+        let text_box = TextBox::new(&self.text, self.bounds, character_style);
+        match self.scroll {
+            Scroll::Tail => text_box.add_plugin(Tail).draw(&mut display.display),
+            Scroll::Fixed => text_box.draw(&mut display.display),
+        }
+        .unwrap();
+
+        self.dirty = false;
+    }
+}
+
+impl<const N: usize> Write for Region<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.write(s) {
+            Ok(())
+        } else {
+            Err(core::fmt::Error)
+        }
+    }
+}
+
+/// Returns at most the last `max` bytes of `text`, cut on a char boundary.
+fn tail(text: &str, max: usize) -> &str {
+    let mut start = text.len().saturating_sub(max);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+/// Up to `R` named text regions, each holding up to `N` bytes of text.
+pub struct Regions<const R: usize, const N: usize> {
+    regions: Vec<Region<N>, R>,
+}
+
+impl<const R: usize, const N: usize> Regions<R, N> {
+    pub fn new() -> Self {
+        Self {
+            regions: Vec::new(),
+        }
+    }
+
+    /// The layout used by `Display`, a "top" console and a "bottom" input
+    /// line.
+    pub fn console() -> Self {
+        let mut regions = Self::new();
+        let _ = regions.add(Region::new("top", TOP_REGION, Scroll::Tail));
+        let _ = regions.add(Region::new("bottom", BOTTOM_REGION, Scroll::Fixed));
+        regions
+    }
+
+    /// Gives the region back if there is no space left or the name is
+    /// already taken.
+    pub fn add(&mut self, region: Region<N>) -> Result<(), Region<N>> {
+        if self.get(region.name).is_some() {
+            return Err(region);
+        }
+        self.regions.push(region)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Region<N>> {
+        let index = self.regions.iter().position(|r| r.name == name)?;
+        Some(self.regions.swap_remove(index))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Region<N>> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Region<N>> {
+        self.regions.iter_mut().find(|r| r.name == name)
+    }
+
+    /// Returns false if there is no region called `name` or the text did not
+    /// fit.
+    pub fn write(&mut self, name: &str, text: &str) -> bool {
+        self.get_mut(name).map_or(false, |r| r.write(text))
+    }
+
+    pub fn draw(&mut self, name: &str, display: &mut Display, clear: bool) {
+        if let Some(region) = self.get_mut(name) {
+            region.draw(display, clear);
+        }
+    }
+
+    /// Redraws the regions that changed since they were last drawn.
+    pub fn draw_dirty(&mut self, display: &mut Display) {
+        for region in self.regions.iter_mut().filter(|r| r.dirty) {
+            region.draw(display, true);
+        }
+    }
+
+    pub fn draw_all(&mut self, display: &mut Display) {
+        display.clear(BG_COLOUR);
+        for region in self.regions.iter_mut() {
+            region.draw(display, false);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region<N>> {
+        self.regions.iter()
+    }
+}
+
+impl<const R: usize, const N: usize> Default for Regions<R, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
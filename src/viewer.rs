@@ -0,0 +1,200 @@
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoFont, MonoTextStyle},
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Baseline, Text},
+};
+
+use crate::display::{Display, BG_COLOUR, DISPLAY_HEIGHT, DISPLAY_WIDTH, TEXT_COLOUR};
+use crate::external_flash::{FLASH_END, FLASH_START};
+use crate::keypad::Key;
+
+/// Pages through long text, wrapping it to the screen width.
+///
+/// The start offset of the first `P` pages is kept so paging backwards does
+/// not have to rescan the text from the beginning, pages past that are found
+/// again by scanning forward from the last known page. `P` must be at least
+/// 1.
+pub struct TextViewer<'a, const P: usize> {
+    text: &'a [u8],
+    page_starts: heapless::Vec<u32, P>,
+    page: usize,
+    start: u32,
+    font: &'static MonoFont<'static>,
+    bounds: Rectangle,
+}
+
+impl<const P: usize> TextViewer<'static, P> {
+    /// Views `length` bytes of external flash starting at `address`, `None`
+    /// if the text would not be inside the external flash.
+    ///
+    /// # Safety
+    ///
+    /// The external flash must be in memory mapped mode for as long as the
+    /// viewer is used, and must not be written to.
+    pub unsafe fn from_flash(address: u32, length: usize) -> Option<Self> {
+        if address < FLASH_START || address > FLASH_END || length > (FLASH_END - address) as usize {
+            return None;
+        }
+        let text = core::slice::from_raw_parts(address as *const u8, length);
+        Some(Self::new(text))
+    }
+}
+
+impl<'a, const P: usize> TextViewer<'a, P> {
+    pub fn new(text: &'a [u8]) -> Self {
+        assert!(P >= 1);
+        let mut page_starts = heapless::Vec::new();
+        let _ = page_starts.push(0);
+        Self {
+            text,
+            page_starts,
+            page: 0,
+            start: 0,
+            font: &FONT_6X10,
+            bounds: Rectangle::new(
+                Point::new(3, 5),
+                Size::new(DISPLAY_WIDTH as u32 - 6, DISPLAY_HEIGHT as u32 - 10),
+            ),
+        }
+    }
+
+    pub fn with_font(mut self, font: &'static MonoFont<'static>) -> Self {
+        self.font = font;
+        self.reset();
+        self
+    }
+
+    pub fn with_bounds(mut self, bounds: Rectangle) -> Self {
+        self.bounds = bounds;
+        self.reset();
+        self
+    }
+
+    fn reset(&mut self) {
+        self.page_starts.clear();
+        let _ = self.page_starts.push(0);
+        self.page = 0;
+        self.start = 0;
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    fn columns(&self) -> usize {
+        let advance = self.font.character_size.width + self.font.character_spacing;
+        ((self.bounds.size.width + self.font.character_spacing) / advance).max(1) as usize
+    }
+
+    fn rows(&self) -> usize {
+        (self.bounds.size.height / self.font.character_size.height).max(1) as usize
+    }
+
+    /// Returns the end of the line starting at `start` and where the next
+    /// line starts, breaking at the last space if a word does not fit.
+    fn next_line(&self, start: usize) -> (usize, usize) {
+        let columns = self.columns();
+        let mut column = 0;
+        let mut last_space = None;
+        for (i, byte) in self.text.iter().enumerate().skip(start) {
+            match byte {
+                b'\n' => return (i, i + 1),
+                // UTF-8 continuation bytes do not take up a column.
+                0x80..=0xbf => continue,
+                _ => {}
+            }
+            if column == columns {
+                if *byte == b' ' {
+                    return (i, i + 1);
+                }
+                return match last_space {
+                    Some(space) => (space, space + 1),
+                    None => (i, i),
+                };
+            }
+            if *byte == b' ' {
+                last_space = Some(i);
+            }
+            column += 1;
+        }
+        (self.text.len(), self.text.len())
+    }
+
+    fn page_end(&self, start: usize) -> usize {
+        (0..self.rows()).fold(start, |line, _| self.next_line(line).1)
+    }
+
+    pub fn is_last_page(&self) -> bool {
+        self.page_end(self.start as usize) >= self.text.len()
+    }
+
+    pub fn next_page(&mut self) -> bool {
+        if self.is_last_page() {
+            return false;
+        }
+        self.start = self.page_end(self.start as usize) as u32;
+        self.page += 1;
+        if self.page == self.page_starts.len() {
+            let _ = self.page_starts.push(self.start);
+        }
+        true
+    }
+
+    pub fn previous_page(&mut self) -> bool {
+        if self.page == 0 {
+            return false;
+        }
+        self.page -= 1;
+        self.start = match self.page_starts.get(self.page) {
+            Some(start) => *start,
+            None => {
+                let known = self.page_starts.len() - 1;
+                let mut start = self.page_starts[known] as usize;
+                for _ in known..self.page {
+                    start = self.page_end(start);
+                }
+                start as u32
+            }
+        };
+        true
+    }
+
+    /// Pages with `Key::Up` and `Key::Down`, returns true if the page changed
+    /// and needs to be drawn again.
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Up => self.previous_page(),
+            Key::Down => self.next_page(),
+            _ => false,
+        }
+    }
+
+    pub fn draw(&self, display: &mut Display) {
+        let character_style = MonoTextStyle::new(self.font, TEXT_COLOUR);
+
+        self.bounds
+            .into_styled(PrimitiveStyleBuilder::new().fill_color(BG_COLOUR).build())
+            .draw(&mut display.display)
+            .unwrap();
+
+        let mut position = self.bounds.top_left;
+        let mut line = self.start as usize;
+        for _ in 0..self.rows() {
+            if line >= self.text.len() {
+                break;
+            }
+            let (end, next) = self.next_line(line);
+            let bytes = &self.text[line..end];
+            let text = match core::str::from_utf8(bytes) {
+                Ok(text) => text,
+                Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+            };
+            Text::with_baseline(text, position, character_style, Baseline::Top)
+                .draw(&mut display.display)
+                .unwrap();
+            position.y += self.font.character_size.height as i32;
+            line = next;
+        }
+    }
+}